@@ -0,0 +1,158 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use crate::{directives, logger};
+
+// Don't let a stuck client block the admin endpoint forever
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Filter strings are short, anything bigger is a mistake
+const MAX_BODY_SIZE: usize = 4096;
+
+/// Serve a tiny HTTP endpoint exposing the current log filters.
+///
+/// - `GET` returns the filters in effect, using the `RUST_LOG` syntax
+/// - `PUT` replaces them with the request body, an empty body restores `RUST_LOG`.
+///   Invalid filters are rejected with `400 Bad Request`, and bodies without a
+///   `Content-Length` header with `411 Length Required`
+///
+/// Changes are reported by a `NOTICE` entry with the filters before and after, and the
+/// address of the client under `config.source`. The path is ignored. Requests are handled
+/// one at a time in a background thread, this function only returns an error if the address
+/// can't be bound.
+/// Requires the `admin` feature.
+/// ## Usage
+/// ```rust,no_run
+/// stackdriver_logger::init();
+/// stackdriver_logger::serve_admin("127.0.0.1:9898").expect("Could not bind admin endpoint");
+/// ```
+/// Then :
+/// ```sh
+/// curl -X PUT -d 'my_app=debug' http://127.0.0.1:9898/
+/// ```
+pub fn serve_admin<A: ToSocketAddrs>(addr: A) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    thread::Builder::new()
+        .name("stackdriver-admin".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Errors only concern this client, keep serving the others
+                let _ = handle(stream);
+            }
+        })?;
+
+    Ok(())
+}
+
+fn handle(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let method = request_line.split_whitespace().next().unwrap_or("");
+
+    // Only Content-Length matters to us, skip everything else.
+    // Chunked bodies aren't supported, they are rejected like a missing length.
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = true;
+            }
+        }
+    }
+    let content_length = content_length.filter(|_| !chunked);
+
+    let (status, body) = match method {
+        "GET" => ("200 OK", logger::current_filters()),
+        "PUT" => match content_length {
+            None => (
+                "411 Length Required",
+                "Content-Length is required".to_owned(),
+            ),
+            Some(length) if length > MAX_BODY_SIZE => {
+                ("413 Payload Too Large", "Filter too long".to_owned())
+            }
+            Some(length) => {
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                let filters = String::from_utf8_lossy(&body);
+                let filters = filters.trim();
+
+                match directives::validate(filters) {
+                    Ok(()) => {
+                        let source = match stream.peer_addr() {
+                            Ok(peer) => format!("admin {}", peer),
+                            Err(_) => "admin".to_owned(),
+                        };
+                        logger::set_override(Some(filters).filter(|f| !f.is_empty()), &source);
+                        ("200 OK", logger::current_filters())
+                    }
+                    Err(directive) => (
+                        "400 Bad Request",
+                        format!("Invalid directive: {}", directive),
+                    ),
+                }
+            }
+        },
+        _ => ("405 Method Not Allowed", "Use GET or PUT".to_owned()),
+    };
+
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        body.len() + 1,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Send a raw request to the handler, returning the status line of the response
+    fn status(request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(request.as_bytes()).unwrap();
+        handle(stream).unwrap();
+
+        let mut response = String::new();
+        BufReader::new(client).read_line(&mut response).unwrap();
+        response.trim().to_owned()
+    }
+
+    #[test]
+    fn rejected_requests() {
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\nContent-Length: 11\r\n\r\nmy_app=loud"),
+            "HTTP/1.1 400 Bad Request"
+        );
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 411 Length Required"
+        );
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\ndebug\r\n0\r\n\r\n"),
+            "HTTP/1.1 411 Length Required"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use log::Level;
+
+use crate::{floor, logger, partitions, Context, Severity};
+
+// Runtime configuration, compared before and after a reload
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    filters: String,
+    format: &'static str,
+    sinks: String,
+    sampling: String,
+    floor: &'static str,
+}
+
+impl Snapshot {
+    // Configuration currently in effect
+    pub(crate) fn capture() -> Self {
+        let sinks = logger::sinks().unwrap_or_default();
+        Self {
+            filters: logger::current_filters(),
+            format: if logger::pretty() { "pretty" } else { "json" },
+            sinks: sinks
+                .iter()
+                .map(|sink| sink.name)
+                .collect::<Vec<_>>()
+                .join(","),
+            sampling: partitions::sample_rates(),
+            floor: floor::current().map_or("none", |level| level.as_str()),
+        }
+    }
+}
+
+// Emit a `NOTICE` entry describing what changed, if anything did.
+// `source` tells what reloaded the configuration, e.g. `admin 10.0.0.1:53422`.
+pub(crate) fn config_changed(source: &str, before: &Snapshot, after: &Snapshot) {
+    let changes = diff(before, after);
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut context = Context::current()
+        .with_field("config.source", source)
+        .with_field(crate::severity::SEVERITY_FIELD, Severity::Notice);
+    for (name, before, after) in &changes {
+        context = context
+            .with_field(format!("config.{}.before", name), before)
+            .with_field(format!("config.{}.after", name), after);
+    }
+
+    let summary = changes
+        .iter()
+        .map(|(name, before, after)| format!("{} '{}' -> '{}'", name, before, after))
+        .collect::<Vec<_>>()
+        .join(", ");
+    context.scope(|| {
+        logger::self_log(
+            Level::Info,
+            format_args!("Logger configuration changed by {}: {}", source, summary),
+        )
+    });
+}
+
+// Settings that differ, with their value before and after
+fn diff<'a>(before: &'a Snapshot, after: &'a Snapshot) -> Vec<(&'static str, &'a str, &'a str)> {
+    let settings = [
+        ("filters", before.filters.as_str(), after.filters.as_str()),
+        ("format", before.format, after.format),
+        ("sinks", before.sinks.as_str(), after.sinks.as_str()),
+        (
+            "sampling",
+            before.sampling.as_str(),
+            after.sampling.as_str(),
+        ),
+        ("floor", before.floor, after.floor),
+    ];
+
+    settings
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_diff() {
+        let before = Snapshot {
+            filters: "info".to_owned(),
+            format: "json",
+            sinks: "stdout".to_owned(),
+            sampling: String::new(),
+            floor: "none",
+        };
+        assert!(diff(&before, &before.clone()).is_empty());
+
+        let after = Snapshot {
+            filters: "my_app=debug".to_owned(),
+            sinks: "stdout,writer".to_owned(),
+            floor: "INFO",
+            ..before.clone()
+        };
+        assert_eq!(
+            diff(&before, &after),
+            [
+                ("filters", "info", "my_app=debug"),
+                ("sinks", "stdout", "stdout,writer"),
+                ("floor", "none", "INFO"),
+            ]
+        );
+    }
+
+    #[test]
+    fn partition_sampling_changes() {
+        let output = crate::testing::install_logger();
+        crate::register_partition("audited", crate::Partition::new().with_sample_rate(0.5));
+        crate::remove_partition("audited");
+
+        let changes = crate::testing::entries(&output);
+        assert!(changes.iter().any(|entry| {
+            entry["config.source"] == "register_partition"
+                && entry["config.sampling.after"]
+                    .as_str()
+                    .is_some_and(|s| s.contains("audited=0.5"))
+                && entry["severity"] == "NOTICE"
+        }));
+        assert!(changes.iter().any(|entry| {
+            entry["config.source"] == "remove_partition"
+                && entry["config.sampling.before"]
+                    .as_str()
+                    .is_some_and(|s| s.contains("audited=0.5"))
+        }));
+    }
+}
@@ -0,0 +1,93 @@
+use log::Level;
+
+use crate::{current_service, logger, resource, Context, Severity, UNKNOWN_SERVICE};
+
+/// Emit a single entry describing how the logger is configured.
+///
+/// The entry reports the output format, the sinks receiving entries, the filters in effect,
+/// the detected platform, the service information and the enabled feature flags under
+/// `config.*` fields, so operators can confirm the effective configuration from the logs
+/// themselves. It is emitted as a `NOTICE` entry, regardless of filters.
+/// The banner is opt-in, initializing the logger doesn't emit it : call this right after.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::log_startup_banner();
+/// ```
+pub fn log_startup_banner() {
+    let format = if logger::pretty() { "pretty" } else { "json" };
+    let service = current_service();
+    let filters = logger::current_filters();
+    let sinks = logger::sinks()
+        .unwrap_or_default()
+        .iter()
+        .map(|sink| sink.name)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let context = Context::current()
+        .with_field("config.format", format)
+        .with_field("config.sinks", &sinks)
+        .with_field("config.filters", &filters)
+        .with_field("config.platform", resource::platform())
+        .with_field(
+            "config.service",
+            service
+                .as_ref()
+                .map_or(UNKNOWN_SERVICE, |s| s.name.as_str()),
+        )
+        .with_field(
+            "config.version",
+            service.as_ref().map_or("", |s| s.version.as_str()),
+        )
+        .with_field("config.features", enabled_features().join(","))
+        .with_field(crate::severity::SEVERITY_FIELD, Severity::Notice);
+
+    context.scope(|| {
+        logger::self_log(
+            Level::Info,
+            format_args!(
+                "Logger initialized, {} format to {} with filters '{}'",
+                format, sinks, filters
+            ),
+        )
+    });
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    let features = [
+        ("customfields", cfg!(feature = "customfields")),
+        ("customfields_serde", cfg!(feature = "customfields_serde")),
+        ("customfields_sval", cfg!(feature = "customfields_sval")),
+        ("customfields_error", cfg!(feature = "customfields_error")),
+        ("nolocation", cfg!(feature = "nolocation")),
+        ("caller", cfg!(feature = "caller")),
+        ("admin", cfg!(feature = "admin")),
+        ("signals", cfg!(feature = "signals")),
+        ("stream", cfg!(feature = "stream")),
+        ("crashbuffer", cfg!(feature = "crashbuffer")),
+    ];
+
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn banner_reports_sinks() {
+        let output = testing::install_logger();
+        let _globals = testing::lock_globals();
+        log_startup_banner();
+
+        let message = "Logger initialized, json format to writer with filters 'trace'";
+        let banners = testing::entries_with_message(&output, message);
+        assert_eq!(banners.last().unwrap()["config.sinks"], "writer");
+    }
+}
@@ -0,0 +1,97 @@
+use serde_json::{json, Value};
+
+const LABELS_KEY: &str = "logging.googleapis.com/labels";
+
+// Keys of the Stackdriver payload mapped to their own column
+const COLUMN_KEYS: [&str; 6] = [
+    "eventTime",
+    "severity",
+    "message",
+    "serviceContext",
+    "reportLocation",
+    LABELS_KEY,
+];
+
+// Turn a Stackdriver payload into a row with a fixed set of columns.
+// Other keys end up in `fields`, a repeated key/value record with string values.
+pub(crate) fn encode(payload: Value) -> Value {
+    let payload = match payload {
+        Value::Object(payload) => payload,
+        payload => return payload,
+    };
+
+    let labels = match payload.get(LABELS_KEY) {
+        Some(Value::Object(labels)) => key_values(labels.iter()),
+        _ => Vec::new(),
+    };
+    let fields = key_values(
+        payload
+            .iter()
+            .filter(|(key, _)| !COLUMN_KEYS.contains(&key.as_str())),
+    );
+
+    let service = payload.get("serviceContext");
+    let location = payload.get("reportLocation");
+    json!({
+        "timestamp": payload.get("eventTime"),
+        "severity": payload.get("severity"),
+        "message": payload.get("message"),
+        "service_name": service.and_then(|s| s.get("service")),
+        "service_version": service.and_then(|s| s.get("version")),
+        "file_path": location.and_then(|l| l.get("filePath")),
+        "line_number": location.and_then(|l| l.get("lineNumber")),
+        "module_path": location.and_then(|l| l.get("modulePath")),
+        "labels": labels,
+        "fields": fields,
+    })
+}
+
+// Values are always strings, so the column type never changes
+fn key_values<'a>(entries: impl Iterator<Item = (&'a String, &'a Value)>) -> Vec<Value> {
+    entries
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_rows() {
+        let payload = json!({
+            "eventTime": "2024-03-01T12:00:00+00:00",
+            "severity": "INFO",
+            "message": "Request served",
+            "serviceContext": { "service": "api" },
+            "reportLocation": null,
+            "logging.googleapis.com/labels": { "module": "api::http" },
+            "count": 3,
+            "user_id": "42",
+        });
+
+        let expected = json!({
+            "timestamp": "2024-03-01T12:00:00+00:00",
+            "severity": "INFO",
+            "message": "Request served",
+            "service_name": "api",
+            "service_version": null,
+            "file_path": null,
+            "line_number": null,
+            "module_path": null,
+            "labels": [{ "key": "module", "value": "api::http" }],
+            "fields": [
+                { "key": "count", "value": "3" },
+                { "key": "user_id", "value": "42" },
+            ],
+        });
+        assert_eq!(encode(payload), expected);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::Level;
+
+use crate::{fingerprint, logger};
+
+// Circuit breaker for repeated errors, disabled by default
+static BREAKER: Mutex<Option<Breaker>> = Mutex::new(None);
+
+// Fingerprints tracked before forgetting idle ones
+const MAX_FINGERPRINTS: usize = 1024;
+
+struct Breaker {
+    threshold: usize,
+    interval: Duration,
+    fingerprints: HashMap<String, State>,
+}
+
+// Errors seen for a fingerprint
+struct State {
+    window: Instant,
+    count: usize,
+    open: Option<Open>,
+}
+
+// An open breaker only lets a summary through every interval
+struct Open {
+    last_summary: Instant,
+    last_seen: Instant,
+    suppressed: usize,
+    // Message of the error that opened the breaker, reported on flush
+    message: String,
+}
+
+/// Replace floods of identical errors with periodic summaries.
+///
+/// Once more than `threshold` errors with the same fingerprint are logged within a second,
+/// further ones are suppressed and a single entry reporting how many were suppressed is
+/// emitted every `interval`. The breaker closes again once the error stops for a whole
+/// interval. Counts not reported yet are reported with the next identical error, or when
+/// the logger is flushed, e.g. when `WorkerGuard` is dropped. Unlike sampling, totals stay
+/// accurate.
+/// Fingerprints are computed as with `set_fingerprints`.
+/// ## Usage
+/// ```rust
+/// use std::time::Duration;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_error_circuit_breaker(10, Duration::from_secs(60));
+/// ```
+pub fn set_error_circuit_breaker(threshold: usize, interval: Duration) {
+    if let Ok(mut breaker) = BREAKER.lock() {
+        *breaker = Some(Breaker {
+            threshold,
+            interval,
+            fingerprints: HashMap::new(),
+        });
+    }
+}
+
+// Whether the record goes through, reports suppressed errors as needed
+pub(crate) fn allow(record: &log::Record<'_>) -> bool {
+    if record.level() != Level::Error {
+        return true;
+    }
+
+    let message = record.args().to_string();
+    let (allowed, summary) = match BREAKER.lock() {
+        Ok(mut breaker) => match breaker.as_mut() {
+            Some(breaker) => {
+                let fingerprint = fingerprint::fingerprint(record, &message);
+                breaker.check(fingerprint, &message, Instant::now())
+            }
+            None => return true,
+        },
+        Err(_) => return true,
+    };
+
+    // Only log once the lock is released
+    if let Some(suppressed) = summary {
+        report(suppressed, &message);
+    }
+
+    allowed
+}
+
+// Report errors suppressed since the last summary, the logger is being flushed
+pub(crate) fn flush() {
+    let pending = match BREAKER.lock() {
+        Ok(mut breaker) => match breaker.as_mut() {
+            Some(breaker) => breaker.take_pending(),
+            None => return,
+        },
+        Err(_) => return,
+    };
+
+    for (suppressed, message) in pending {
+        report(suppressed, &message);
+    }
+}
+
+fn report(suppressed: usize, message: &str) {
+    logger::self_log(
+        Level::Error,
+        format_args!(
+            "{} identical errors were suppressed: {}",
+            suppressed, message
+        ),
+    );
+}
+
+impl Breaker {
+    // Whether an error goes through, and how many errors to report as suppressed
+    fn check(&mut self, fingerprint: String, message: &str, now: Instant) -> (bool, Option<usize>) {
+        if self.fingerprints.len() >= MAX_FINGERPRINTS {
+            let interval = self.interval;
+            self.fingerprints.retain(|_, state| match &state.open {
+                Some(open) => now.duration_since(open.last_seen) < interval,
+                None => now.duration_since(state.window) < interval,
+            });
+        }
+
+        let state = self.fingerprints.entry(fingerprint).or_insert(State {
+            window: now,
+            count: 0,
+            open: None,
+        });
+
+        let mut summary = None;
+        if let Some(open) = &mut state.open {
+            // Quiet for a whole interval, close the breaker
+            if now.duration_since(open.last_seen) >= self.interval {
+                summary = Some(open.suppressed).filter(|s| *s > 0);
+                state.open = None;
+                state.window = now;
+                state.count = 0;
+            } else {
+                open.suppressed += 1;
+                open.last_seen = now;
+                if now.duration_since(open.last_summary) >= self.interval {
+                    summary = Some(open.suppressed);
+                    open.suppressed = 0;
+                    open.last_summary = now;
+                }
+                return (false, summary);
+            }
+        }
+
+        if now.duration_since(state.window) >= Duration::from_secs(1) {
+            state.window = now;
+            state.count = 0;
+        }
+        state.count += 1;
+
+        if state.count > self.threshold {
+            state.open = Some(Open {
+                last_summary: now,
+                last_seen: now,
+                suppressed: 1,
+                message: message.to_owned(),
+            });
+            return (false, summary);
+        }
+
+        (true, summary)
+    }
+
+    // Counts not reported yet with their message, they're reset
+    fn take_pending(&mut self) -> Vec<(usize, String)> {
+        self.fingerprints
+            .values_mut()
+            .filter_map(|state| state.open.as_mut())
+            .filter(|open| open.suppressed > 0)
+            .map(|open| (std::mem::take(&mut open.suppressed), open.message.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_and_close() {
+        let mut breaker = Breaker {
+            threshold: 2,
+            interval: Duration::from_secs(10),
+            fingerprints: HashMap::new(),
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut check = |ms| breaker.check("a".to_owned(), "Failed", at(ms));
+
+        assert_eq!(check(0), (true, None));
+        assert_eq!(check(100), (true, None));
+        assert_eq!(check(200), (false, None));
+        assert_eq!(check(5_000), (false, None));
+        assert_eq!(check(10_200), (false, Some(3)));
+        assert_eq!(check(11_000), (false, None));
+
+        // Quiet for a whole interval
+        assert_eq!(check(30_000), (true, Some(1)));
+        assert_eq!(check(30_100), (true, None));
+    }
+
+    #[test]
+    fn pending_on_flush() {
+        let mut breaker = Breaker {
+            threshold: 1,
+            interval: Duration::from_secs(10),
+            fingerprints: HashMap::new(),
+        };
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.check("a".to_owned(), "Failed", now);
+        }
+
+        // The error stopped, nothing else would report these
+        assert_eq!(breaker.take_pending(), [(2, "Failed".to_owned())]);
+        assert!(breaker.take_pending().is_empty());
+        let quiet = now + Duration::from_secs(20);
+        assert_eq!(breaker.check("a".to_owned(), "Failed", quiet), (true, None));
+    }
+}
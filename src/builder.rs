@@ -0,0 +1,530 @@
+#[cfg(feature = "customfields")]
+use crate::{FloatFormat, KeyCase};
+use std::{collections::BTreeMap, io::Write};
+
+use log::Level;
+
+use crate::{
+    directives, logger, resource,
+    timestamp::{TimestampFormat, TimestampPrecision},
+    writer::{Batch, SharedWriter, WorkerGuard},
+    InitError, Service, Severity, SERVICE,
+};
+
+// Timestamp key parsed by the GKE logging agent
+const GKE_TIMESTAMP_KEY: &str = "time";
+
+/// Where entries are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    /// Standard output
+    Stdout,
+
+    /// Standard error, this is the default
+    #[default]
+    Stderr,
+
+    /// `WARN` and `ERROR` entries to standard error, other entries to standard output.
+    /// GKE and Cloud Run partly infer severities from the stream
+    Split,
+}
+
+/// How entries are encoded, only JSON output is affected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Stackdriver structured logging, this is the default
+    #[default]
+    Stackdriver,
+
+    /// Flat wide events for Honeycomb's events API, with `trace.*`, `service.*` and `code.*`
+    /// fields and a numeric `duration_ms`
+    Honeycomb,
+
+    /// Flat rows with a fixed set of columns, for BigQuery sinks. Custom and context fields
+    /// are emitted as a repeated `fields` record of string keys and values
+    BigQuery,
+}
+
+/// Configure the logger before installing it, see `builder()`.
+#[derive(Clone)]
+pub struct Builder {
+    pub(crate) service: Option<Service>,
+    pub(crate) report_location: bool,
+    pub(crate) target: Target,
+    pub(crate) format: Format,
+    pub(crate) filters: Option<String>,
+    pub(crate) writer: Option<SharedWriter>,
+    pub(crate) non_blocking: bool,
+    pub(crate) batch: Option<Batch>,
+    pub(crate) timestamp_key: Option<String>,
+    pub(crate) color_severity: bool,
+    pub(crate) timestamp_format: TimestampFormat,
+    pub(crate) timestamp_precision: TimestampPrecision,
+    #[cfg_attr(
+        not(all(feature = "pretty_env_logger", debug_assertions)),
+        allow(dead_code)
+    )]
+    pub(crate) local_timestamps: bool,
+    #[cfg(feature = "customfields")]
+    key_case: Option<KeyCase>,
+    #[cfg(feature = "customfields")]
+    nested_keys: Option<bool>,
+    #[cfg(feature = "customfields")]
+    float_format: Option<FloatFormat>,
+    max_entry_size: Option<usize>,
+    target_label: Option<bool>,
+    json_passthrough: Option<bool>,
+    resource_attributes: Option<bool>,
+    fingerprints: Option<bool>,
+    source_link: Option<(String, String)>,
+    source_location: Option<bool>,
+    labels: Option<BTreeMap<String, String>>,
+    trace_project: Option<String>,
+    severity_mapping: Vec<(Level, Severity)>,
+}
+
+/// Configure the logger with chainable options, then install it.
+///
+/// Options left unset keep their defaults, or the value of the matching `set_*` function.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::{Service, Target};
+///
+/// let service = Service {
+///     name: "My Service".to_owned(),
+///     version: "2.3.1".to_owned(),
+/// };
+///
+/// stackdriver_logger::builder()
+///     .service(service)
+///     .target(Target::Stdout)
+///     .filters("info,my_app=debug")
+///     .target_label(true)
+///     .init();
+/// info!("We're all set here !");
+/// ```
+pub fn builder() -> Builder {
+    Builder::new()
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    /// Create a builder with the default options, like `init()`.
+    pub fn new() -> Self {
+        Self {
+            service: None,
+            report_location: true,
+            target: Target::default(),
+            format: Format::default(),
+            filters: None,
+            writer: None,
+            non_blocking: false,
+            batch: None,
+            timestamp_key: None,
+            color_severity: false,
+            timestamp_format: TimestampFormat::default(),
+            timestamp_precision: TimestampPrecision::default(),
+            local_timestamps: false,
+            #[cfg(feature = "customfields")]
+            key_case: None,
+            #[cfg(feature = "customfields")]
+            nested_keys: None,
+            #[cfg(feature = "customfields")]
+            float_format: None,
+            max_entry_size: None,
+            target_label: None,
+            json_passthrough: None,
+            resource_attributes: None,
+            fingerprints: None,
+            source_link: None,
+            source_location: None,
+            labels: None,
+            trace_project: None,
+            severity_mapping: Vec::new(),
+        }
+    }
+
+    /// Create a builder with the recommended options for Cloud Run, see `init_cloud_run`.
+    pub fn cloud_run() -> Self {
+        let mut builder = Self::new().target(Target::Split).source_location(true);
+        builder.service = resource::cloud_run_service();
+        builder.trace_project = resource::project_id();
+        builder
+    }
+
+    /// Create a builder with the recommended options for GKE, see `init_gke`.
+    pub fn gke() -> Self {
+        let mut builder = Self::new()
+            .target(Target::Stdout)
+            .timestamp_key(GKE_TIMESTAMP_KEY)
+            .source_location(true)
+            .resource_attributes(true);
+        builder.service = Service::from_env();
+        builder.trace_project = resource::project_id();
+        builder
+    }
+
+    /// Service information reported in `serviceContext`.
+    pub fn service(mut self, service: Service) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    /// Report the location of each entry, enabled by default.
+    pub fn report_location(mut self, enabled: bool) -> Self {
+        self.report_location = enabled;
+        self
+    }
+
+    /// Where entries are written, standard error by default.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Write entries to the given writer instead of the target, e.g. a pipe, a socket or
+    /// a test buffer. Entries are always formatted as JSON, even in debug builds.
+    /// ## Usage
+    /// ```rust
+    /// use std::{fs::File, io::BufWriter};
+    ///
+    /// let file = File::create(std::env::temp_dir().join("my_app.log")).unwrap();
+    /// stackdriver_logger::builder()
+    ///     .writer(BufWriter::new(file))
+    ///     .init();
+    /// ```
+    pub fn writer<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.writer = Some(SharedWriter::new(writer));
+        self
+    }
+
+    /// Write entries from a dedicated thread, so logging doesn't wait on standard output or
+    /// the custom writer. Entries are still formatted on the calling thread, they are dropped
+    /// if the writer thread falls too far behind. `log::logger().flush()` waits for pending
+    /// entries. Pretty output in debug builds is always blocking.
+    /// ## Usage
+    /// ```rust
+    /// stackdriver_logger::builder().non_blocking(true).init();
+    /// ```
+    pub fn non_blocking(mut self, enabled: bool) -> Self {
+        self.non_blocking = enabled;
+        self
+    }
+
+    /// Accumulate entries and write them in batches, once they reach `max_bytes` or every
+    /// `interval`, instead of one write per entry. Pending entries are written by
+    /// `log::logger().flush()`, call it before exiting. Pretty output in debug builds is never
+    /// batched.
+    /// ## Usage
+    /// ```rust
+    /// use stackdriver_logger::Batch;
+    ///
+    /// stackdriver_logger::builder().batch(Batch::default()).init();
+    /// log::logger().flush();
+    /// ```
+    pub fn batch(mut self, batch: Batch) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    /// Prefix pretty entries with a timestamp in the local time zone, with its UTC offset so
+    /// daylight saving time changes stay readable. JSON entries are always in UTC.
+    /// Only affects debug builds with the `pretty_env_logger` feature.
+    pub fn local_timestamps(mut self, enabled: bool) -> Self {
+        self.local_timestamps = enabled;
+        self
+    }
+
+    /// How entries are encoded, Stackdriver JSON by default.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Color the severity of JSON entries written to a terminal, to make raw entries easier
+    /// to read locally. Output written to pipes, files or custom writers is left unchanged.
+    /// Requires the `termcolor` feature.
+    pub fn color_severity(mut self, enabled: bool) -> Self {
+        self.color_severity = enabled;
+        self
+    }
+
+    /// Key of the entry timestamp, `eventTime` by default, e.g. `time` or `timestamp` for
+    /// ingestion paths expecting them. Only affects the Stackdriver format.
+    pub fn timestamp_key(mut self, key: &str) -> Self {
+        self.timestamp_key = Some(key.to_owned());
+        self
+    }
+
+    /// How the entry timestamp is represented, RFC3339 by default.
+    /// Only affects the Stackdriver format.
+    /// ## Usage
+    /// ```rust
+    /// use stackdriver_logger::TimestampFormat;
+    ///
+    /// stackdriver_logger::builder()
+    ///     .timestamp_key("timestamp")
+    ///     .timestamp_format(TimestampFormat::Object)
+    ///     .init();
+    /// ```
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Fractional digits of the entry timestamp, as many as needed by default. Nanoseconds
+    /// are rarely useful and bloat log volume, e.g. `Millis` is usually enough.
+    /// Only affects the Stackdriver format.
+    /// ## Usage
+    /// ```rust
+    /// use stackdriver_logger::TimestampPrecision;
+    ///
+    /// stackdriver_logger::builder()
+    ///     .timestamp_precision(TimestampPrecision::Millis)
+    ///     .init();
+    /// ```
+    pub fn timestamp_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = precision;
+        self
+    }
+
+    /// Filters using the `RUST_LOG` syntax, used instead of the `RUST_LOG` variable.
+    /// Unlike `RUST_LOG`, invalid directives make the initialization fail.
+    pub fn filters(mut self, filters: &str) -> Self {
+        self.filters = Some(filters.to_owned());
+        self
+    }
+
+    /// See `set_key_case`. Requires the `customfields` feature.
+    #[cfg(feature = "customfields")]
+    pub fn key_case(mut self, case: KeyCase) -> Self {
+        self.key_case = Some(case);
+        self
+    }
+
+    /// See `set_nested_keys`. Requires the `customfields` feature.
+    #[cfg(feature = "customfields")]
+    pub fn nested_keys(mut self, enabled: bool) -> Self {
+        self.nested_keys = Some(enabled);
+        self
+    }
+
+    /// See `set_float_format`. Requires the `customfields` feature.
+    #[cfg(feature = "customfields")]
+    pub fn float_format(mut self, format: FloatFormat) -> Self {
+        self.float_format = Some(format);
+        self
+    }
+
+    /// See `set_max_entry_size`.
+    pub fn max_entry_size(mut self, max_bytes: usize) -> Self {
+        self.max_entry_size = Some(max_bytes);
+        self
+    }
+
+    /// See `set_target_label`.
+    pub fn target_label(mut self, enabled: bool) -> Self {
+        self.target_label = Some(enabled);
+        self
+    }
+
+    /// See `set_json_passthrough`.
+    pub fn json_passthrough(mut self, enabled: bool) -> Self {
+        self.json_passthrough = Some(enabled);
+        self
+    }
+
+    /// See `set_resource_attributes`.
+    pub fn resource_attributes(mut self, enabled: bool) -> Self {
+        self.resource_attributes = Some(enabled);
+        self
+    }
+
+    /// See `set_fingerprints`.
+    pub fn fingerprints(mut self, enabled: bool) -> Self {
+        self.fingerprints = Some(enabled);
+        self
+    }
+
+    /// Add a label to every entry, see `set_static_labels`.
+    /// ## Usage
+    /// ```rust
+    /// stackdriver_logger::builder()
+    ///     .label("team", "payments")
+    ///     .label("region", "eu")
+    ///     .init();
+    /// ```
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// See `set_trace_project`.
+    pub fn trace_project(mut self, project_id: &str) -> Self {
+        self.trace_project = Some(project_id.to_owned());
+        self
+    }
+
+    /// Emit a `log` level with another severity, see `set_severity_mapping`.
+    /// ## Usage
+    /// ```rust
+    /// use log::Level;
+    /// use stackdriver_logger::Severity;
+    ///
+    /// stackdriver_logger::builder()
+    ///     .severity_mapping(Level::Trace, Severity::Default)
+    ///     .severity_mapping(Level::Warn, Severity::Notice)
+    ///     .init();
+    /// ```
+    pub fn severity_mapping(mut self, level: Level, severity: Severity) -> Self {
+        self.severity_mapping.push((level, severity));
+        self
+    }
+
+    /// See `set_source_location`.
+    pub fn source_location(mut self, enabled: bool) -> Self {
+        self.source_location = Some(enabled);
+        self
+    }
+
+    /// See `set_source_link`.
+    pub fn source_link(mut self, template: &str, revision: &str) -> Self {
+        self.source_link = Some((template.to_owned(), revision.to_owned()));
+        self
+    }
+
+    /// Install the logger, panics if a logger is already installed.
+    pub fn init(self) {
+        self.try_init()
+            .expect("Could not initialize stackdriver_logger");
+    }
+
+    /// Install the logger, fails if a logger is already installed or the filters are invalid.
+    /// Options are only applied once the logger is installed, on failure the installed logger
+    /// keeps its configuration, use `try_upgrade` to replace it.
+    /// ## Usage
+    /// ```rust
+    /// use stackdriver_logger::InitError;
+    ///
+    /// match stackdriver_logger::builder().filters("info").try_init() {
+    ///     Ok(()) => {}
+    ///     // Nothing was changed, the existing logger is left as is
+    ///     Err(InitError::AlreadyInitialized) => eprintln!("Logging was already initialized"),
+    ///     Err(e) => panic!("Could not initialize logging: {}", e),
+    /// }
+    /// ```
+    pub fn try_init(self) -> Result<(), InitError> {
+        self.validate()?;
+        logger::install(crate::build_loggers(&self))?;
+        self.apply();
+        Ok(())
+    }
+
+    /// Install the logger and return a guard flushing pending entries when dropped, for
+    /// non-blocking or batched output. Panics if a logger is already installed.
+    /// ## Usage
+    /// ```rust
+    /// let _guard = stackdriver_logger::builder().non_blocking(true).init_guarded();
+    /// log::info!("Written before the process exits");
+    /// ```
+    pub fn init_guarded(self) -> WorkerGuard {
+        self.try_init_guarded()
+            .expect("Could not initialize stackdriver_logger")
+    }
+
+    /// Fallible version of `init_guarded`.
+    pub fn try_init_guarded(self) -> Result<WorkerGuard, InitError> {
+        self.validate()?;
+        let loggers = crate::build_loggers(&self);
+        let workers = loggers.workers.clone();
+        logger::install(loggers)?;
+        self.apply();
+        Ok(WorkerGuard::new(workers))
+    }
+
+    /// Replace the loggers installed by `init_minimal` or a previous builder, see `upgrade`.
+    pub fn upgrade(self) {
+        self.try_upgrade()
+            .expect("Could not initialize stackdriver_logger");
+    }
+
+    /// Replace the loggers installed by `init_minimal` or a previous builder, see `upgrade`.
+    /// Fails if the filters are invalid, or if another logger is installed.
+    pub fn try_upgrade(self) -> Result<(), InitError> {
+        self.validate()?;
+        if !logger::replace(crate::build_loggers(&self)) {
+            return self.try_init();
+        }
+        self.apply();
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), InitError> {
+        match &self.filters {
+            Some(filters) => directives::validate(filters).map_err(InitError::InvalidFilter),
+            None => Ok(()),
+        }
+    }
+
+    // Apply the options backed by global settings
+    pub(crate) fn apply(&self) {
+        if let Ok(mut current) = SERVICE.write() {
+            *current = self.service.clone();
+        }
+
+        #[cfg(feature = "customfields")]
+        {
+            if let Some(case) = self.key_case {
+                crate::set_key_case(case);
+            }
+            if let Some(enabled) = self.nested_keys {
+                crate::set_nested_keys(enabled);
+            }
+            if let Some(format) = self.float_format {
+                crate::set_float_format(format);
+            }
+        }
+        if let Some(max_bytes) = self.max_entry_size {
+            crate::set_max_entry_size(max_bytes);
+        }
+        if let Some(enabled) = self.target_label {
+            crate::set_target_label(enabled);
+        }
+        if let Some(enabled) = self.json_passthrough {
+            crate::set_json_passthrough(enabled);
+        }
+        if let Some(enabled) = self.resource_attributes {
+            crate::set_resource_attributes(enabled);
+        }
+        if let Some(enabled) = self.fingerprints {
+            crate::set_fingerprints(enabled);
+        }
+        if let Some(labels) = &self.labels {
+            let labels: Vec<(&str, &str)> = labels
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            crate::set_static_labels(&labels);
+        }
+        if let Some(project_id) = &self.trace_project {
+            crate::set_trace_project(project_id);
+        }
+        if let Some(enabled) = self.source_location {
+            crate::set_source_location(enabled);
+        }
+        for (level, severity) in &self.severity_mapping {
+            crate::set_severity_mapping(*level, *severity);
+        }
+        if let Some((template, revision)) = &self.source_link {
+            crate::set_source_link(template, revision);
+        }
+    }
+}
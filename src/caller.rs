@@ -0,0 +1,174 @@
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde_json::{json, Value};
+
+#[cfg(feature = "caller")]
+use std::sync::RwLock;
+
+// Module prefixes of logging facades wrapping this crate
+#[cfg(feature = "caller")]
+static SKIP_LIST: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+// Emit the location as a `logging.googleapis.com/sourceLocation` field
+static SOURCE_LOCATION: AtomicBool = AtomicBool::new(false);
+
+// Where a record comes from, as reported to Stackdriver
+#[cfg_attr(feature = "nolocation", allow(dead_code))]
+pub(crate) struct Location<'a> {
+    pub(crate) file: Option<Cow<'a, str>>,
+    pub(crate) line: Option<u32>,
+    pub(crate) module_path: Option<Cow<'a, str>>,
+}
+
+/// Report locations past the given logging facade modules.
+///
+/// When this crate is wrapped behind your own logging functions, `reportLocation` points at
+/// the wrapper. With a skip list, the stack is walked at log time to find the first frame
+/// outside the listed module prefixes, and its location is reported instead.
+/// This captures a backtrace for each record, resolution is best effort and relies on debug
+/// symbols being available. Requires the `caller` feature.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_caller_skip_list(&["my_app::logging"]);
+/// ```
+#[cfg(feature = "caller")]
+pub fn set_caller_skip_list(prefixes: &[&str]) {
+    if let Ok(mut list) = SKIP_LIST.write() {
+        *list = prefixes.iter().map(|p| (*p).to_owned()).collect();
+    }
+}
+
+/// Emit the `logging.googleapis.com/sourceLocation` special field with every entry.
+///
+/// Cloud Logging shows this field natively in the Logs Explorer, with the file, the line
+/// and the module path as function. It is emitted alongside `reportLocation`, disable the
+/// latter with `Builder::report_location` to only keep this one. Has no effect with the
+/// `nolocation` feature.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_source_location(true);
+/// ```
+pub fn set_source_location(enabled: bool) {
+    SOURCE_LOCATION.store(enabled, Ordering::Relaxed);
+}
+
+// Locate a record, looking past facade modules when configured
+#[cfg_attr(feature = "nolocation", allow(dead_code))]
+pub(crate) fn locate<'a>(record: &log::Record<'a>) -> Location<'a> {
+    #[cfg(feature = "caller")]
+    if let Some(location) = locate_caller() {
+        return location;
+    }
+
+    Location {
+        file: record.file().map(Cow::Borrowed),
+        line: record.line(),
+        module_path: record.module_path().map(Cow::Borrowed),
+    }
+}
+
+impl Location<'_> {
+    // Special field of the structured logging agent, when enabled. Lines are strings there.
+    #[cfg_attr(feature = "nolocation", allow(dead_code))]
+    pub(crate) fn source_location(&self) -> Option<Value> {
+        if !SOURCE_LOCATION.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(json!({
+            "file": self.file,
+            "line": self.line.map(|line| line.to_string()),
+            "function": self.module_path,
+        }))
+    }
+}
+
+#[cfg(feature = "caller")]
+fn locate_caller() -> Option<Location<'static>> {
+    let skip_list = SKIP_LIST.read().ok()?;
+    if skip_list.is_empty() {
+        return None;
+    }
+
+    // Frames are walked from the innermost one, the caller is right after the facade.
+    // Without debug symbols for that frame, fall back to the record location.
+    let mut in_facade = false;
+    let mut done = false;
+    let mut location = None;
+    backtrace::trace(|frame| {
+        backtrace::resolve_frame(frame, |symbol| {
+            if done {
+                return;
+            }
+
+            let name = match symbol.name() {
+                Some(name) => format!("{:#}", name),
+                None => return,
+            };
+
+            if skip_list.iter().any(|p| name.starts_with(p.as_str())) {
+                in_facade = true;
+            } else if in_facade {
+                done = true;
+                location = symbol.filename().map(|file| Location {
+                    file: Some(Cow::Owned(file.to_string_lossy().into_owned())),
+                    line: symbol.lineno(),
+                    module_path: Some(Cow::Owned(module_path(&name).to_owned())),
+                });
+            }
+        });
+
+        !done
+    });
+
+    location
+}
+
+// Drop the function name from a demangled symbol, closures included
+#[cfg(feature = "caller")]
+fn module_path(symbol: &str) -> &str {
+    let path = symbol.trim_end_matches("::{{closure}}");
+    path.rsplit_once("::").map_or(path, |(module, _)| module)
+}
+
+// Locations aren't captured with `nolocation`
+#[cfg(all(test, not(feature = "nolocation")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_location_field() {
+        let _globals = crate::testing::lock_globals();
+        let record = log::Record::builder()
+            .args(format_args!("Located"))
+            .file(Some("src/main.rs"))
+            .line(Some(42))
+            .module_path(Some("my_app"))
+            .build();
+        let pipeline = crate::Pipeline::new();
+        let key = "logging.googleapis.com/sourceLocation";
+
+        assert!(pipeline.process(&record).get(key).is_none());
+        set_source_location(true);
+        let entry = pipeline.process(&record);
+        set_source_location(false);
+
+        assert_eq!(
+            entry[key],
+            json!({ "file": "src/main.rs", "line": "42", "function": "my_app" })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "caller")]
+    fn symbol_module_path() {
+        assert_eq!(module_path("my_app::handlers::run"), "my_app::handlers");
+        assert_eq!(module_path("my_app::main::{{closure}}"), "my_app");
+        assert_eq!(module_path("main"), "main");
+    }
+}
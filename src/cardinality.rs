@@ -0,0 +1,154 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Only set once a limit is configured
+static GUARD: Mutex<Option<Guard>> = Mutex::new(None);
+
+/// What happens to new values of a key over its cardinality limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardinalityAction {
+    /// Replace the value with a short stable hash, e.g. `hash:9f2c41d3`
+    Hash,
+
+    /// Leave the field or label out of the entry
+    Drop,
+}
+
+/// Protect label and field keys from cardinality explosions.
+///
+/// Each label, context field and custom field key may take at most `max_values` distinct
+/// values within a window. Past that, new values are hashed or dropped until the window
+/// ends, while known values are still emitted as is. A `WARNING` entry reports the first
+/// key going over its limit in each window. This protects log-based metrics built on
+/// labels or fields from unbounded cardinality.
+/// ## Usage
+/// ```rust
+/// use std::time::Duration;
+/// use stackdriver_logger::CardinalityAction;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_cardinality_limit(1000, Duration::from_secs(3600), CardinalityAction::Hash);
+/// ```
+pub fn set_cardinality_limit(max_values: usize, window: Duration, action: CardinalityAction) {
+    if let Ok(mut guard) = GUARD.lock() {
+        *guard = Some(Guard::new(max_values, window, action, Instant::now()));
+    }
+}
+
+// Value to emit for a key, `None` if it should be dropped
+pub(crate) fn guard<'a>(key: &str, value: &'a str) -> Option<Cow<'a, str>> {
+    let (value, exceeded) = match GUARD.lock() {
+        Ok(mut guard) => match guard.as_mut() {
+            Some(guard) => guard.check(key, value, Instant::now()),
+            None => return Some(Cow::Borrowed(value)),
+        },
+        Err(_) => return Some(Cow::Borrowed(value)),
+    };
+
+    // The lock is released, the warning goes through the guard like any other entry
+    if exceeded {
+        crate::logger::self_log(
+            log::Level::Warn,
+            format_args!(
+                "Key {} exceeded its cardinality limit, new values are altered",
+                key
+            ),
+        );
+    }
+
+    value
+}
+
+// Distinct values seen per key during the current window
+struct Guard {
+    max_values: usize,
+    window: Duration,
+    action: CardinalityAction,
+    started: Instant,
+    values: HashMap<String, HashSet<String>>,
+    exceeded: HashSet<String>,
+}
+
+impl Guard {
+    fn new(max_values: usize, window: Duration, action: CardinalityAction, now: Instant) -> Self {
+        Self {
+            max_values,
+            window,
+            action,
+            started: now,
+            values: HashMap::new(),
+            exceeded: HashSet::new(),
+        }
+    }
+
+    // Returns the value to emit, and whether the key just went over its limit
+    fn check<'a>(
+        &mut self,
+        key: &str,
+        value: &'a str,
+        now: Instant,
+    ) -> (Option<Cow<'a, str>>, bool) {
+        if now.duration_since(self.started) >= self.window {
+            self.started = now;
+            self.values.clear();
+            self.exceeded.clear();
+        }
+
+        let values = self.values.entry(key.to_owned()).or_default();
+        if values.contains(value) {
+            return (Some(Cow::Borrowed(value)), false);
+        }
+        if values.len() < self.max_values {
+            values.insert(value.to_owned());
+            return (Some(Cow::Borrowed(value)), false);
+        }
+
+        let exceeded = self.exceeded.insert(key.to_owned());
+        let value = match self.action {
+            CardinalityAction::Hash => Some(Cow::Owned(format!("hash:{:08x}", fnv1a(value)))),
+            CardinalityAction::Drop => None,
+        };
+        (value, exceeded)
+    }
+}
+
+// Stable across processes and releases, unlike the std hasher
+fn fnv1a(value: &str) -> u32 {
+    value.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cardinality_limit() {
+        let start = Instant::now();
+        let mut guard = Guard::new(2, Duration::from_secs(60), CardinalityAction::Hash, start);
+
+        let mut check = |value, now| guard.check("user_id", value, now);
+        assert_eq!(check("a", start), (Some(Cow::Borrowed("a")), false));
+        assert_eq!(check("b", start), (Some(Cow::Borrowed("b")), false));
+        assert_eq!(
+            check("c", start),
+            (Some(Cow::Borrowed("hash:e60c2c52")), true)
+        );
+        assert_eq!(
+            check("d", start),
+            (Some(Cow::Borrowed("hash:e10c2473")), false)
+        );
+        assert_eq!(check("a", start), (Some(Cow::Borrowed("a")), false));
+
+        let later = start + Duration::from_secs(60);
+        assert_eq!(check("c", later), (Some(Cow::Borrowed("c")), false));
+
+        let mut guard = Guard::new(0, Duration::from_secs(60), CardinalityAction::Drop, start);
+        assert_eq!(guard.check("user_id", "a", start), (None, true));
+    }
+}
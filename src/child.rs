@@ -0,0 +1,79 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::Child,
+    thread::{self, JoinHandle},
+};
+
+use log::Level;
+
+use crate::Context;
+
+/// Handle on the threads forwarding a child process output.
+pub struct ForwardedOutput {
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl ForwardedOutput {
+    /// Wait until both streams are closed, usually when the child exits.
+    pub fn join(self) {
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Forward a child process output through the logger, one entry per line.
+///
+/// Only piped streams are forwarded. Each line becomes the message of an entry using `target`
+/// as its target, `INFO` for stdout and `WARNING` for stderr, with `child.pid` and
+/// `child.stream` fields. Tools writing to stdout no longer break JSON parsing of your logs.
+/// ## Usage
+/// ```rust,no_run
+/// use std::process::{Command, Stdio};
+///
+/// stackdriver_logger::init();
+///
+/// let mut child = Command::new("ffmpeg")
+///     .stdout(Stdio::piped())
+///     .stderr(Stdio::piped())
+///     .spawn()
+///     .unwrap();
+///
+/// let output = stackdriver_logger::forward_child_output(&mut child, "ffmpeg");
+/// child.wait().unwrap();
+/// output.join();
+/// ```
+pub fn forward_child_output(child: &mut Child, target: &str) -> ForwardedOutput {
+    let pid = child.id();
+    let mut handles = Vec::with_capacity(2);
+
+    if let Some(stdout) = child.stdout.take() {
+        handles.push(forward(stdout, pid, "stdout", Level::Info, target));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        handles.push(forward(stderr, pid, "stderr", Level::Warn, target));
+    }
+
+    ForwardedOutput { handles }
+}
+
+fn forward<R: Read + Send + 'static>(
+    stream: R,
+    pid: u32,
+    name: &'static str,
+    level: Level,
+    target: &str,
+) -> JoinHandle<()> {
+    let target = target.to_owned();
+    let context = Context::current()
+        .with_field("child.pid", pid)
+        .with_field("child.stream", name);
+
+    thread::spawn(move || {
+        let _guard = context.enter();
+        for line in BufReader::new(stream).split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line);
+            log::log!(target: &target, level, "{}", line.trim_end_matches('\r'));
+        }
+    })
+}
@@ -0,0 +1,265 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Timestamps are formatted with chrono when the `chrono` feature is enabled, which is the
+// default, or with std only otherwise. Both backends produce the same UTC output.
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+// Seconds and nanoseconds since the Unix epoch, seconds are negative before it
+pub(crate) fn unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => (elapsed.as_secs() as i64, elapsed.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+#[cfg(any(test, not(feature = "chrono")))]
+fn from_unix(seconds: i64, nanos: u32) -> Option<SystemTime> {
+    use std::time::Duration;
+
+    let time = match seconds {
+        0.. => UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))?,
+        _ => UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))?,
+    };
+    time.checked_add(Duration::from_nanos(nanos.into()))
+}
+
+// Days since the Unix epoch, in UTC
+pub(crate) fn day(time: SystemTime) -> i64 {
+    unix(time).0.div_euclid(SECONDS_PER_DAY)
+}
+
+// RFC3339 in UTC, with 0, 3, 6 or 9 fractional digits
+#[cfg(feature = "chrono")]
+pub(crate) fn to_rfc3339(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn to_rfc3339(time: SystemTime) -> String {
+    let digits = match unix(time).1 {
+        0 => 0,
+        nanos if nanos % 1_000_000 == 0 => 3,
+        nanos if nanos % 1_000 == 0 => 6,
+        _ => 9,
+    };
+    to_rfc3339_fixed(time, digits)
+}
+
+// RFC3339 in UTC, with exactly 0, 3, 6 or 9 fractional digits, extra digits are truncated
+#[cfg(feature = "chrono")]
+pub(crate) fn to_rfc3339_fixed(time: SystemTime, digits: u32) -> String {
+    use chrono::SecondsFormat;
+
+    let format = match digits {
+        0 => SecondsFormat::Secs,
+        3 => SecondsFormat::Millis,
+        6 => SecondsFormat::Micros,
+        _ => SecondsFormat::Nanos,
+    };
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339_opts(format, false)
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn to_rfc3339_fixed(time: SystemTime, digits: u32) -> String {
+    let (seconds, nanos) = unix(time);
+    match digits.min(9) {
+        0 => format!("{}+00:00", format_utc(seconds)),
+        digits => format!(
+            "{}.{:0width$}+00:00",
+            format_utc(seconds),
+            nanos / 10u32.pow(9 - digits),
+            width = digits as usize
+        ),
+    }
+}
+
+#[cfg(feature = "chrono")]
+pub(crate) fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(SystemTime::from)
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let bytes = value.as_bytes();
+    let digits = |range: std::ops::Range<usize>| digits_of(value.get(range)?);
+    let separated =
+        |at: usize, expected: &[u8]| bytes.get(at).is_some_and(|b| expected.contains(b));
+
+    if !separated(4, b"-") || !separated(7, b"-") || !separated(10, b"Tt ") {
+        return None;
+    }
+    if !separated(13, b":") || !separated(16, b":") {
+        return None;
+    }
+    let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        // Digits past nanoseconds are truncated
+        let kept = &fraction[..len.min(9)];
+        nanos = kept.parse::<u32>().ok()? * 10u32.pow(9 - kept.len() as u32);
+        rest = &fraction[len..];
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let (hours, minutes) = (digits_of(rest.get(1..3)?)?, digits_of(rest.get(4..6)?)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            match rest.as_bytes()[0] {
+                b'+' => hours * 3600 + minutes * 60,
+                b'-' => -(hours * 3600 + minutes * 60),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    // Leap seconds are folded into the next second, like SystemTime does
+    let seconds =
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second
+            - offset;
+    from_unix(seconds, nanos)
+}
+
+#[cfg(not(feature = "chrono"))]
+fn digits_of(part: &str) -> Option<i64> {
+    match part.bytes().all(|b| b.is_ascii_digit()) {
+        true => part.parse().ok(),
+        false => None,
+    }
+}
+
+// Human-readable local time for the pretty output
+#[cfg(feature = "chrono")]
+#[cfg_attr(
+    not(all(feature = "pretty_env_logger", debug_assertions)),
+    allow(dead_code)
+)]
+pub(crate) fn local(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y-%m-%d %H:%M:%S%.3f%:z")
+        .to_string()
+}
+
+// Without chrono there is no time zone database, UTC is used instead
+#[cfg(not(feature = "chrono"))]
+#[cfg_attr(
+    not(all(feature = "pretty_env_logger", debug_assertions)),
+    allow(dead_code)
+)]
+pub(crate) fn local(time: SystemTime) -> String {
+    let (seconds, nanos) = unix(time);
+    format!("{}.{:03}+00:00", format_utc(seconds), nanos / 1_000_000).replacen('T', " ", 1)
+}
+
+#[cfg(not(feature = "chrono"))]
+fn format_utc(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// Proleptic Gregorian calendar conversions, from Howard Hinnant's date algorithms
+#[cfg(not(feature = "chrono"))]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(not(feature = "chrono"))]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(not(feature = "chrono"))]
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_roundtrip() {
+        let time = parse_rfc3339("2024-03-01T13:30:00.250+01:30").unwrap();
+        assert_eq!(unix(time), (1709294400, 250_000_000));
+        assert_eq!(to_rfc3339(time), "2024-03-01T12:00:00.250+00:00");
+        assert_eq!(day(time), 19783);
+
+        let before_epoch = parse_rfc3339("1969-12-31T23:59:59.5Z").unwrap();
+        assert_eq!(unix(before_epoch), (-1, 500_000_000));
+        assert_eq!(to_rfc3339(before_epoch), "1969-12-31T23:59:59.500+00:00");
+
+        let precise = from_unix(1709294400, 123_456_789).unwrap();
+        assert_eq!(to_rfc3339(precise), "2024-03-01T12:00:00.123456789+00:00");
+        assert_eq!(parse_rfc3339(&to_rfc3339(precise)), Some(precise));
+        assert_eq!(to_rfc3339_fixed(precise, 0), "2024-03-01T12:00:00+00:00");
+        assert_eq!(
+            to_rfc3339_fixed(precise, 6),
+            "2024-03-01T12:00:00.123456+00:00"
+        );
+        assert_eq!(
+            to_rfc3339_fixed(time, 9),
+            "2024-03-01T12:00:00.250000000+00:00"
+        );
+
+        assert_eq!(parse_rfc3339("2024-02-30T12:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-03-01 12:00:00"), None);
+    }
+}
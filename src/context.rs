@@ -0,0 +1,620 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt::{self, Write},
+    marker::PhantomData,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::Level;
+
+use crate::{http::SharedHttpRequest, logger, HttpRequest, Operation};
+
+thread_local! {
+    // Context entered on the current thread
+    static CURRENT: RefCell<Context> = RefCell::new(Context::new());
+
+    // Lazy field values of the record being emitted, see `LazyCache`
+    static LAZY_VALUES: RefCell<Option<BTreeMap<String, String>>> = const { RefCell::new(None) };
+
+    // Whether a lazy field is being computed, records it emits don't get lazy fields
+    static COMPUTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Fields attached to every record emitted while the context is entered.
+///
+/// Contexts are cheap to clone and can be sent to other threads, through channels or
+/// with [`spawn_logged`], so an operation or correlation id established for a request
+/// follows the work it triggers.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::Context;
+///
+/// stackdriver_logger::init();
+///
+/// let context = Context::current().with_field("correlation_id", "4bf92f35");
+/// let _guard = context.enter();
+/// info!("This entry has a correlation_id field");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    fields: Arc<BTreeMap<String, Field>>,
+    budget: Option<Arc<BudgetState>>,
+    operation: Option<Operation>,
+    http_request: Option<SharedHttpRequest>,
+}
+
+// Value of a context field, lazy ones are computed once for each record that is emitted
+#[derive(Clone)]
+enum Field {
+    Value(String),
+    Lazy(Arc<dyn Fn() -> String + Send + Sync>),
+}
+
+impl Field {
+    // Never called with the current context borrowed, lazy closures may log
+    fn value(&self, key: &str) -> Option<String> {
+        let compute = match self {
+            Field::Value(value) => return Some(value.clone()),
+            Field::Lazy(compute) => compute,
+        };
+
+        if COMPUTING.with(Cell::get) {
+            return None;
+        }
+        let cached = LAZY_VALUES.with(|values| values.borrow().as_ref()?.get(key).cloned());
+        if cached.is_some() {
+            return cached;
+        }
+
+        struct Computing;
+
+        impl Drop for Computing {
+            fn drop(&mut self) {
+                COMPUTING.with(|computing| computing.set(false));
+            }
+        }
+
+        COMPUTING.with(|computing| computing.set(true));
+        let computing = Computing;
+        let value = compute();
+        drop(computing);
+
+        LAZY_VALUES.with(|values| {
+            if let Some(values) = values.borrow_mut().as_mut() {
+                values.insert(key.to_owned(), value.clone());
+            }
+        });
+        Some(value)
+    }
+}
+
+// Keeps lazy field values while a record is emitted, so they're computed once per record
+pub(crate) struct LazyCache(Option<BTreeMap<String, String>>);
+
+impl LazyCache {
+    pub(crate) fn enter() -> Self {
+        Self(LAZY_VALUES.with(|values| values.replace(Some(BTreeMap::new()))))
+    }
+}
+
+impl Drop for LazyCache {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        LAZY_VALUES.with(|values| values.replace(previous));
+    }
+}
+
+impl fmt::Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Value(value) => value.fmt(f),
+            Field::Lazy(_) => f.write_str("<lazy>"),
+        }
+    }
+}
+
+/// Limits on what a context can log, see [`Context::with_budget`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+    /// Maximum number of entries, unlimited if `None`
+    pub max_entries: Option<usize>,
+
+    /// Maximum number of message bytes, unlimited if `None`
+    pub max_bytes: Option<usize>,
+}
+
+// Usage of a budget, shared by every clone of the context
+#[derive(Debug)]
+struct BudgetState {
+    limits: Budget,
+    entries: AtomicUsize,
+    bytes: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl Context {
+    /// Create an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Context entered on the current thread, empty if none.
+    pub fn current() -> Self {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Add a field to this context, replacing any previous value for the same key.
+    pub fn with_field<K: Into<String>, V: fmt::Display>(mut self, key: K, value: V) -> Self {
+        Arc::make_mut(&mut self.fields).insert(key.into(), Field::Value(value.to_string()));
+        self
+    }
+
+    /// Add a field computed only for records that pass the level, target and sampling
+    /// filters, see [`lazy_field`].
+    pub fn with_lazy_field<K, F, V>(mut self, key: K, compute: F) -> Self
+    where
+        K: Into<String>,
+        F: Fn() -> V + Send + Sync + 'static,
+        V: fmt::Display,
+    {
+        let compute = Arc::new(move || compute().to_string());
+        Arc::make_mut(&mut self.fields).insert(key.into(), Field::Lazy(compute));
+        self
+    }
+
+    /// Limit what can be logged within this context, and the contexts derived from it.
+    ///
+    /// Once the budget is exhausted, `INFO`, `DEBUG` and `TRACE` entries are dropped while
+    /// warnings and errors are still emitted. When the context is dropped, a single `WARNING`
+    /// entry reports how many entries were dropped. This protects against requests logging
+    /// in a loop.
+    /// ## Usage
+    /// ```rust
+    /// use log::info;
+    /// use stackdriver_logger::{Budget, Context};
+    ///
+    /// stackdriver_logger::init();
+    ///
+    /// let budget = Budget {
+    ///     max_entries: Some(100),
+    ///     max_bytes: Some(64 * 1024),
+    /// };
+    /// Context::current().with_budget(budget).scope(|| {
+    ///     for i in 0..1000 {
+    ///         info!("Only the first 100 are emitted: {}", i);
+    ///     }
+    /// });
+    /// ```
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(Arc::new(BudgetState {
+            limits: budget,
+            entries: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }));
+        self
+    }
+
+    /// Group the entries emitted within this context under an operation, see [`Operation`].
+    pub fn with_operation(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Emit an `httpRequest` field with the entries of this context, see [`HttpRequest`].
+    pub fn with_http_request(mut self, request: HttpRequest) -> Self {
+        self.http_request = Some(Arc::new(request));
+        self
+    }
+
+    /// Enter this context on the current thread until the guard is dropped.
+    pub fn enter(&self) -> ContextGuard {
+        let previous = CURRENT.with(|current| current.replace(self.clone()));
+        ContextGuard {
+            previous: Some(previous),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Run a closure with this context entered.
+    pub fn scope<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        let _guard = self.enter();
+        f()
+    }
+
+    /// Wrap a closure so it runs with this context entered, on whichever thread calls it.
+    ///
+    /// Thread pools like rayon run closures on worker threads, which don't see the context
+    /// of the thread submitting the work. Binding the closure carries the context along.
+    /// ## Usage
+    /// ```rust
+    /// use log::info;
+    /// use stackdriver_logger::Context;
+    ///
+    /// stackdriver_logger::init();
+    ///
+    /// let context = Context::current().with_field("request_id", "4bf92f35");
+    /// let process = context.bind(|item: u32| info!("Processing {}", item));
+    /// // e.g. items.par_iter().for_each(process) with rayon
+    /// std::thread::scope(|s| {
+    ///     for item in 0..4 {
+    ///         let process = &process;
+    ///         s.spawn(move || process(item));
+    ///     }
+    /// });
+    /// ```
+    pub fn bind<F, A, R>(&self, f: F) -> impl Fn(A) -> R + Send + Sync
+    where
+        F: Fn(A) -> R + Send + Sync,
+    {
+        let context = self.clone();
+        move |arg| context.scope(|| f(arg))
+    }
+
+    // Values of the fields, computing lazy ones
+    pub(crate) fn fields(&self) -> BTreeMap<String, String> {
+        self.fields
+            .iter()
+            .filter_map(|(key, field)| Some((key.clone(), field.value(key)?)))
+            .collect()
+    }
+}
+
+impl BudgetState {
+    // Account for a record, returns false if it should be dropped
+    fn consume(&self, record: &log::Record<'_>) -> bool {
+        let entries = self.entries.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes = self.bytes.fetch_add(message_len(record), Ordering::Relaxed);
+
+        let exhausted = self.limits.max_entries.is_some_and(|max| entries > max)
+            || self.limits.max_bytes.is_some_and(|max| bytes >= max);
+
+        if exhausted && record.level() > Level::Warn {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Drop for BudgetState {
+    fn drop(&mut self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            logger::self_log(
+                Level::Warn,
+                format_args!(
+                    "Log budget exceeded, {} low-severity entries were dropped",
+                    dropped
+                ),
+            );
+        }
+    }
+}
+
+// Value of a field, from the record custom fields first then the current context
+#[cfg_attr(not(feature = "customfields"), allow(unused_variables))]
+pub(crate) fn field_value(record: &log::Record<'_>, name: &str) -> Option<String> {
+    #[cfg(feature = "customfields")]
+    if let Some(value) = record.key_values().get(log::kv::Key::from_str(name)) {
+        return Some(value.to_string());
+    }
+
+    let field = CURRENT
+        .try_with(|current| current.borrow().fields.get(name).cloned())
+        .ok()
+        .flatten()?;
+    field.value(name)
+}
+
+// Operation of the current context, if any
+pub(crate) fn current_operation() -> Option<Operation> {
+    CURRENT
+        .try_with(|current| current.borrow().operation.clone())
+        .ok()
+        .flatten()
+}
+
+// Request of the current context, if any
+pub(crate) fn current_http_request() -> Option<SharedHttpRequest> {
+    CURRENT
+        .try_with(|current| current.borrow().http_request.clone())
+        .ok()
+        .flatten()
+}
+
+// Check the budget of the current context, if any
+pub(crate) fn within_budget(record: &log::Record<'_>) -> bool {
+    CURRENT
+        .try_with(|current| match &current.borrow().budget {
+            Some(budget) => budget.consume(record),
+            None => true,
+        })
+        .unwrap_or(true)
+}
+
+// Size of a formatted message, without allocating it
+fn message_len(record: &log::Record<'_>) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = write!(counter, "{}", record.args());
+    counter.0
+}
+
+/// Restores the previous context when dropped.
+pub struct ContextGuard {
+    previous: Option<Context>,
+
+    // Contexts are entered per thread, the guard must be dropped on the same one
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            // Dropping the context may report its budget, which reads the current context
+            let entered = CURRENT.with(|current| current.replace(previous));
+            drop(entered);
+        }
+    }
+}
+
+/// Run a closure with the attempt of a retry loop attached to its records.
+///
+/// Records emitted within the closure carry `retry.attempt`, `retry.max` and `retry.final`
+/// fields, attempts count from 1. Cloud Logging queries can't compare two fields, filter
+/// on `retry.final="true"` to only see failures of the last attempt.
+/// ## Usage
+/// ```rust
+/// use log::warn;
+///
+/// stackdriver_logger::init();
+///
+/// let max = 3;
+/// for attempt in 1..=max {
+///     stackdriver_logger::with_attempt(attempt, max, || warn!("Upstream call failed"));
+/// }
+/// ```
+pub fn with_attempt<F: FnOnce() -> R, R>(attempt: u32, max: u32, f: F) -> R {
+    Context::current()
+        .with_field("retry.attempt", attempt)
+        .with_field("retry.max", max)
+        .with_field("retry.final", attempt >= max)
+        .scope(f)
+}
+
+/// Add a field computed only if a record is emitted, for expensive debug context.
+///
+/// The closure runs once for each record emitted within the returned context, once it passed
+/// the level, target and sampling filters, so it costs nothing when debug entries are disabled.
+/// It can log, records it emits don't get lazy fields.
+/// ## Usage
+/// ```rust
+/// use log::debug;
+///
+/// fn dump_state() -> String {
+///     "expensive to compute".to_owned()
+/// }
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::lazy_field("dump", dump_state).scope(|| {
+///     debug!("Only computed if debug entries are enabled");
+/// });
+/// ```
+pub fn lazy_field<K, F, V>(key: K, compute: F) -> Context
+where
+    K: Into<String>,
+    F: Fn() -> V + Send + Sync + 'static,
+    V: fmt::Display,
+{
+    Context::current().with_lazy_field(key, compute)
+}
+
+/// Spawn a thread inheriting the current context.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::Context;
+///
+/// stackdriver_logger::init();
+///
+/// let _guard = Context::current().with_field("operation_id", "job-42").enter();
+/// stackdriver_logger::spawn_logged(|| info!("Still part of job-42"))
+///     .join()
+///     .unwrap();
+/// ```
+pub fn spawn_logged<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let context = Context::current();
+    thread::spawn(move || context.scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_contexts() {
+        let outer = Context::new().with_field("a", "outer");
+        let _outer = outer.enter();
+
+        Context::current().with_field("b", 2).scope(|| {
+            let fields = Context::current().fields();
+            assert_eq!(fields["a"], "outer");
+            assert_eq!(fields["b"], "2");
+        });
+
+        let fields = Context::current().fields();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields["a"], "outer");
+    }
+
+    #[test]
+    fn budget_drops_low_severity_entries() {
+        let budget = Budget {
+            max_entries: Some(2),
+            max_bytes: None,
+        };
+        let context = Context::new().with_budget(budget);
+        let record = |level| {
+            log::Record::builder()
+                .args(format_args!("entry"))
+                .level(level)
+                .build()
+        };
+
+        context.scope(|| {
+            assert!(within_budget(&record(Level::Info)));
+            assert!(within_budget(&record(Level::Debug)));
+            assert!(!within_budget(&record(Level::Info)));
+            assert!(within_budget(&record(Level::Error)));
+        });
+
+        let dropped = context
+            .budget
+            .as_ref()
+            .unwrap()
+            .dropped
+            .load(Ordering::Relaxed);
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn budget_report_on_exit() {
+        let output = crate::testing::install_logger();
+        let budget = Budget {
+            max_entries: Some(1),
+            max_bytes: None,
+        };
+
+        // The entered clone is the last one, leaving the context reports the budget
+        let guard = Context::new().with_budget(budget).enter();
+        for _ in 0..3 {
+            log::info!("Over budget");
+        }
+        drop(guard);
+
+        let message = "Log budget exceeded, 2 low-severity entries were dropped";
+        let reports = crate::testing::entries_with_message(&output, message);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0]["severity"], "WARNING");
+    }
+
+    #[test]
+    fn budget_ignores_sampled_out_entries() {
+        let output = crate::testing::install_logger();
+        let _globals = crate::testing::lock_globals();
+        let budget = Budget {
+            max_entries: Some(1),
+            max_bytes: None,
+        };
+        let trace =
+            crate::TraceContext::from_cloud_trace_header("105445aa7843bc8bf206b12000100000/1;o=0")
+                .unwrap();
+
+        crate::set_trace_sampled_verbosity(true);
+        trace.context().with_budget(budget).scope(|| {
+            log::debug!("Sampled out");
+            log::debug!("Sampled out");
+            log::info!("Within sampled budget");
+        });
+        crate::set_trace_sampled_verbosity(false);
+
+        let entries = crate::testing::entries_with_message(&output, "Within sampled budget");
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn bound_closures_carry_context() {
+        let context = Context::new().with_field("request_id", "42");
+        let read = context.bind(|key: &str| Context::current().fields().get(key).cloned());
+
+        let field = thread::scope(|s| s.spawn(|| read("request_id")).join().unwrap());
+        assert_eq!(field.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn retry_attempts() {
+        let fields = with_attempt(3, 3, || Context::current().fields());
+        assert_eq!(fields["retry.attempt"], "3");
+        assert_eq!(fields["retry.max"], "3");
+        assert_eq!(fields["retry.final"], "true");
+
+        let fields = with_attempt(1, 3, || Context::current().fields());
+        assert_eq!(fields["retry.final"], "false");
+    }
+
+    #[test]
+    fn spawned_threads_inherit_context() {
+        let _guard = Context::new().with_field("operation_id", "42").enter();
+        let fields = spawn_logged(|| Context::current().fields()).join().unwrap();
+
+        assert_eq!(fields["operation_id"], "42");
+    }
+
+    #[test]
+    fn lazy_fields() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let loggers = crate::build_loggers(
+            &crate::builder()
+                .filters("warn")
+                .writer(std::io::sink())
+                .report_location(false),
+        );
+        let log = |level| {
+            let record = log::Record::builder()
+                .args(format_args!("Lazy"))
+                .level(level)
+                .build();
+            loggers.default.log(&record);
+        };
+
+        lazy_field("lazy_dump", || CALLS.fetch_add(1, Ordering::SeqCst)).scope(|| {
+            log(Level::Debug);
+            assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+            log(Level::Error);
+            assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn lazy_fields_computed_once() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let output = crate::testing::install_logger();
+        let severity = || {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            Context::new().scope(|| log::info!("Computing a lazy severity"));
+            "NOTICE"
+        };
+
+        // Read both by the severity and the context fields
+        lazy_field("severity", severity).scope(|| log::info!("Lazy severity"));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        let entries = crate::testing::entries_with_message(&output, "Lazy severity");
+        assert_eq!(entries[0]["severity"], "NOTICE");
+        let entries = crate::testing::entries_with_message(&output, "Computing a lazy severity");
+        assert_eq!(entries[0]["severity"], "INFO");
+    }
+}
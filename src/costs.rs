@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use log::Level;
+
+use crate::{clock, logger};
+
+// Daily budget per target in bytes, 0 when disabled
+static DAILY_BUDGET: AtomicU64 = AtomicU64::new(0);
+
+static COSTS: Mutex<Option<Costs>> = Mutex::new(None);
+
+/// Warn when a target exceeds an estimated daily ingestion volume.
+///
+/// The size of each formatted entry is accounted to its target, the counters are reset
+/// every day at midnight UTC. The first time a target goes over budget on a given day,
+/// a single `WARNING` entry reports it, helping to catch cost regressions from new chatty
+/// code paths. Entries are never dropped. Use `0` to disable the budget.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_daily_target_budget(512 * 1024 * 1024);
+/// ```
+pub fn set_daily_target_budget(max_bytes: u64) {
+    DAILY_BUDGET.store(max_bytes, Ordering::Relaxed);
+}
+
+// Account for a formatted entry
+pub(crate) fn record(target: &str, bytes: usize) {
+    let budget = DAILY_BUDGET.load(Ordering::Relaxed);
+    if budget == 0 {
+        return;
+    }
+
+    let today = clock::day(SystemTime::now());
+    let exceeded = match COSTS.lock() {
+        Ok(mut costs) => {
+            costs
+                .get_or_insert_with(|| Costs::new(today))
+                .add(today, target, bytes as u64, budget)
+        }
+        Err(_) => None,
+    };
+
+    // The lock is released, the warning is accounted like any other entry
+    if let Some(total) = exceeded {
+        logger::self_log(
+            Level::Warn,
+            format_args!(
+                "Target {} exceeded its daily log budget, {} bytes emitted today",
+                target, total
+            ),
+        );
+    }
+}
+
+// Estimated bytes emitted per target during a single day
+struct Costs {
+    // Days since the Unix epoch
+    day: i64,
+    bytes: HashMap<String, u64>,
+}
+
+impl Costs {
+    fn new(day: i64) -> Self {
+        Self {
+            day,
+            bytes: HashMap::new(),
+        }
+    }
+
+    // Returns the total when the target just went over budget
+    fn add(&mut self, day: i64, target: &str, bytes: u64, budget: u64) -> Option<u64> {
+        if day != self.day {
+            *self = Self::new(day);
+        }
+
+        let total = match self.bytes.get_mut(target) {
+            Some(total) => total,
+            None => self.bytes.entry(target.to_owned()).or_default(),
+        };
+        let previous = *total;
+        *total += bytes;
+
+        Some(*total).filter(|total| previous <= budget && *total > budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_costs() {
+        let day = 19783;
+        let mut costs = Costs::new(day);
+
+        assert_eq!(costs.add(day, "my_app", 60, 100), None);
+        assert_eq!(costs.add(day, "my_app::db", 60, 100), None);
+        assert_eq!(costs.add(day, "my_app", 60, 100), Some(120));
+        assert_eq!(costs.add(day, "my_app", 60, 100), None);
+
+        let next_day = day + 1;
+        assert_eq!(costs.add(next_day, "my_app", 60, 100), None);
+        assert_eq!(costs.add(next_day, "my_app", 60, 100), Some(120));
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor},
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::Mutex,
+};
+
+use serde_json::Value;
+
+// Buffer entries are written to, if enabled
+static CRASH_BUFFER: Mutex<Option<CrashBuffer>> = Mutex::new(None);
+
+// Identifies crash buffer files, followed by the format version
+const MAGIC: &[u8; 8] = b"SDLCRSH1";
+
+// Magic, state, slot count, slot size, padding and write count
+const HEADER_LEN: usize = 32;
+
+// Entries larger than a slot, length included, aren't kept
+const SLOT_LEN: usize = 4096;
+
+// The buffer is open until the process exits cleanly
+const STATE_OPEN: u32 = 1;
+const STATE_CLOSED: u32 = 0;
+
+/// Keep the last `entries` formatted entries in a file, so they survive crashes.
+///
+/// The file is a ring of fixed-size slots written with positioned writes, it isn't memory
+/// mapped. Each entry costs two `pwrite` system calls, made while holding a global lock, on
+/// the thread emitting it. Writes go to the page cache of the kernel, entries are kept if
+/// the process panics, aborts or is killed, e.g. by the OOM killer, but not if the machine
+/// loses power. Call [`recover_crash_buffer`] on startup, before this function which resets
+/// the file, and [`close_crash_buffer`] on clean exits. Entries larger than 4KiB aren't kept.
+/// Only JSON output is affected. Requires the `crashbuffer` feature, Unix only.
+/// ## Usage
+/// ```rust
+/// let path = std::env::temp_dir().join("my_app.crash");
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::recover_crash_buffer(&path).unwrap();
+/// stackdriver_logger::set_crash_buffer(&path, 256).unwrap();
+/// log::info!("Kept until the next clean exit");
+/// stackdriver_logger::close_crash_buffer();
+/// ```
+pub fn set_crash_buffer<P: AsRef<Path>>(path: P, entries: usize) -> io::Result<()> {
+    let buffer = CrashBuffer::create(path.as_ref(), entries.max(1))?;
+    if let Ok(mut current) = CRASH_BUFFER.lock() {
+        *current = Some(buffer);
+    }
+    Ok(())
+}
+
+/// Mark the crash buffer as cleanly closed and stop writing to it, see [`set_crash_buffer`].
+/// `WorkerGuard` does this when dropped.
+pub fn close_crash_buffer() {
+    if let Ok(mut current) = CRASH_BUFFER.lock() {
+        if let Some(mut buffer) = current.take() {
+            buffer.set_state(STATE_CLOSED);
+        }
+    }
+}
+
+/// Emit the entries left in a crash buffer by a previous process that didn't exit cleanly.
+///
+/// Entries are emitted as they were formatted, with a `recovered` field set to `true`, see
+/// `replay`. Returns the number of recovered entries, `0` if the file doesn't exist or the
+/// previous process exited cleanly. Fails if no logger is installed.
+pub fn recover_crash_buffer<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let lines: String = leftover_entries(&content)
+        .into_iter()
+        .filter_map(|entry| {
+            let mut entry: Value = serde_json::from_str(&entry).ok()?;
+            entry
+                .as_object_mut()?
+                .insert("recovered".to_owned(), Value::Bool(true));
+            Some(entry.to_string() + "\n")
+        })
+        .collect();
+
+    crate::replay(Cursor::new(lines))
+}
+
+// Keep a formatted entry in the crash buffer, if enabled
+pub(crate) fn record(entry: &str) {
+    if let Ok(mut current) = CRASH_BUFFER.lock() {
+        if let Some(buffer) = current.as_mut() {
+            buffer.push(entry.as_bytes());
+        }
+    }
+}
+
+// Entries of a buffer left open, oldest first
+fn leftover_entries(content: &[u8]) -> Vec<String> {
+    if content.len() < HEADER_LEN || &content[..8] != MAGIC || read_u32(content, 8) != STATE_OPEN {
+        return Vec::new();
+    }
+
+    let slots = read_u32(content, 12) as usize;
+    let slot_len = read_u32(content, 16) as usize;
+    let written = read_u64(content, 24) as usize;
+    if slots == 0 || slot_len <= 4 || content.len() < HEADER_LEN + slots * slot_len {
+        return Vec::new();
+    }
+
+    (written.saturating_sub(slots)..written)
+        .filter_map(|index| {
+            let slot = &content[HEADER_LEN + (index % slots) * slot_len..][..slot_len];
+            let len = read_u32(slot, 0) as usize;
+            let entry = slot.get(4..4 + len)?;
+            String::from_utf8(entry.to_vec()).ok()
+        })
+        .collect()
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(value)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(value)
+}
+
+// Ring of fixed-size slots in a preallocated file, written with `pwrite` instead of a memory
+// mapping so the crate stays free of unsafe code. Positioned writes land in the page cache,
+// they survive the process without a flush.
+struct CrashBuffer {
+    file: File,
+    slots: usize,
+    written: u64,
+}
+
+impl CrashBuffer {
+    fn create(path: &Path, slots: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_LEN + slots * SLOT_LEN) as u64)?;
+
+        let mut header = [0; HEADER_LEN];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&STATE_OPEN.to_le_bytes());
+        header[12..16].copy_from_slice(&(slots as u32).to_le_bytes());
+        header[16..20].copy_from_slice(&(SLOT_LEN as u32).to_le_bytes());
+        file.write_all_at(&header, 0)?;
+
+        Ok(Self {
+            file,
+            slots,
+            written: 0,
+        })
+    }
+
+    fn set_state(&mut self, state: u32) {
+        let _ = self.file.write_all_at(&state.to_le_bytes(), 8);
+    }
+
+    fn push(&mut self, entry: &[u8]) {
+        if entry.len() > SLOT_LEN - 4 {
+            return;
+        }
+
+        let mut slot = Vec::with_capacity(4 + entry.len());
+        slot.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        slot.extend_from_slice(entry);
+        let start = HEADER_LEN + (self.written as usize % self.slots) * SLOT_LEN;
+        if self.file.write_all_at(&slot, start as u64).is_err() {
+            return;
+        }
+
+        // Only count the entry once it's complete
+        self.written += 1;
+        let _ = self.file.write_all_at(&self.written.to_le_bytes(), 24);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftover_ring() {
+        let path = std::env::temp_dir().join(format!("crash-{}.buffer", std::process::id()));
+        let mut buffer = CrashBuffer::create(&path, 2).unwrap();
+        for message in ["first", "second", "third"] {
+            buffer.push(format!("{{\"message\":\"{}\"}}", message).as_bytes());
+        }
+        buffer.push(&[b'x'; SLOT_LEN]);
+
+        let content = fs::read(&path).unwrap();
+        assert_eq!(
+            leftover_entries(&content),
+            ["{\"message\":\"second\"}", "{\"message\":\"third\"}"]
+        );
+
+        buffer.set_state(STATE_CLOSED);
+        assert!(leftover_entries(&fs::read(&path).unwrap()).is_empty());
+        drop(buffer);
+        fs::remove_file(path).unwrap();
+    }
+}
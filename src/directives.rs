@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use log::{LevelFilter, Record};
+
+use crate::context;
+
+// Enable a level for records carrying a given field value, e.g. `tenant=acme=debug`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FieldDirective {
+    key: String,
+    value: String,
+    level: LevelFilter,
+}
+
+impl FieldDirective {
+    pub(crate) fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn matches(&self, record: &Record<'_>) -> bool {
+        record.level() <= self.level
+            && context::field_value(record, &self.key).as_deref() == Some(self.value.as_str())
+    }
+}
+
+// Split field directives out of a RUST_LOG-like spec, returning what env_logger understands.
+// Field directives have two `=`, like `tenant=acme=debug`.
+pub(crate) fn split(spec: &str) -> (String, Vec<FieldDirective>) {
+    let (directives, regex) = match spec.split_once('/') {
+        Some((directives, regex)) => (directives, Some(regex)),
+        None => (spec, None),
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = Vec::new();
+    for directive in directives.split(',').map(str::trim) {
+        match parse_field_directive(directive) {
+            Some(field) => fields.push(field),
+            None if !directive.is_empty() => rest.push(directive),
+            None => {}
+        }
+    }
+
+    let mut filters = rest.join(",");
+    if let Some(regex) = regex {
+        filters.push('/');
+        filters.push_str(regex);
+    }
+
+    (filters, fields)
+}
+
+// Check every directive of a RUST_LOG-like spec, returning the first invalid one
+pub(crate) fn validate(spec: &str) -> Result<(), String> {
+    let directives = spec
+        .split_once('/')
+        .map_or(spec, |(directives, _)| directives);
+
+    for directive in directives.split(',').map(str::trim) {
+        if directive.is_empty() || parse_field_directive(directive).is_some() {
+            continue;
+        }
+
+        let valid = match directive.split_once('=') {
+            Some((target, level)) => {
+                !target.trim().is_empty() && LevelFilter::from_str(level.trim()).is_ok()
+            }
+            // A bare level or a target
+            None => !directive.contains(char::is_whitespace),
+        };
+        if !valid {
+            return Err(directive.to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+// Whether any directive enables this record
+pub(crate) fn matches(directives: &[FieldDirective], record: &Record<'_>) -> bool {
+    directives.iter().any(|d| d.matches(record))
+}
+
+fn parse_field_directive(directive: &str) -> Option<FieldDirective> {
+    let mut parts = directive.splitn(3, '=');
+    let key = parts.next()?.trim();
+    let value = parts.next()?.trim();
+    let level = LevelFilter::from_str(parts.next()?.trim()).ok()?;
+
+    if key.is_empty() {
+        return None;
+    }
+
+    Some(FieldDirective {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        level,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_field_directives() {
+        let (filters, fields) = split("info,tenant=acme=debug,my_app=trace/foo");
+        assert_eq!(filters, "info,my_app=trace/foo");
+        assert_eq!(
+            fields,
+            vec![FieldDirective {
+                key: "tenant".to_owned(),
+                value: "acme".to_owned(),
+                level: LevelFilter::Debug,
+            }]
+        );
+
+        let (filters, fields) = split("warn");
+        assert_eq!(filters, "warn");
+        assert!(fields.is_empty());
+    }
+
+    #[test]
+    fn validate_directives() {
+        assert_eq!(validate("info,my_app=debug,tenant=acme=trace/foo"), Ok(()));
+        assert_eq!(validate("my_app"), Ok(()));
+        assert_eq!(validate("my_app=loud"), Err("my_app=loud".to_owned()));
+        assert_eq!(validate("info,=debug"), Err("=debug".to_owned()));
+    }
+
+    #[test]
+    fn match_context_fields() {
+        let (_, fields) = split("tenant=acme=debug");
+        let record = |level| Record::builder().level(level).target("my_app").build();
+
+        crate::Context::new()
+            .with_field("tenant", "acme")
+            .scope(|| {
+                assert!(matches(&fields, &record(log::Level::Debug)));
+                assert!(!matches(&fields, &record(log::Level::Trace)));
+            });
+
+        assert!(!matches(&fields, &record(log::Level::Debug)));
+    }
+}
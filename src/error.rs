@@ -0,0 +1,36 @@
+use std::{error::Error, fmt};
+
+use log::SetLoggerError;
+
+/// Reasons the logger couldn't be initialized.
+#[derive(Debug)]
+pub enum InitError {
+    /// A global logger is already installed, by this crate or another one
+    AlreadyInitialized,
+
+    /// A filter directive given to the builder is invalid
+    InvalidFilter(String),
+
+    /// The configuration, e.g. the `Cargo.toml` read by `try_init_with_cargo!`, is invalid
+    Config(String),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::AlreadyInitialized => f.write_str("a logger is already installed"),
+            InitError::InvalidFilter(directive) => {
+                write!(f, "invalid filter directive '{}'", directive)
+            }
+            InitError::Config(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+impl Error for InitError {}
+
+impl From<SetLoggerError> for InitError {
+    fn from(_: SetLoggerError) -> Self {
+        InitError::AlreadyInitialized
+    }
+}
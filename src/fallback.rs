@@ -0,0 +1,88 @@
+use std::{
+    fmt::{self, Write},
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::LogLevel;
+
+// Keep fallback entries small, the original message may be the reason formatting failed
+const MAX_MESSAGE_LEN: usize = 1024;
+
+// Minimal plain text entry, used when the structured entry couldn't be formatted.
+// The message is rendered on a best effort basis and truncated.
+pub(crate) fn plain_entry(record: &log::Record<'_>) -> String {
+    let mut message = Truncated(String::new());
+    let rendered = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _ = write!(message, "{}", record.args());
+    }));
+    if rendered.is_err() {
+        message.0 = "<unformattable message>".to_owned();
+    }
+
+    format!(
+        "{} {}: {}",
+        LogLevel(record.level()),
+        record.target(),
+        message.0
+    )
+}
+
+// Stop writing once the max length is reached
+struct Truncated(String);
+
+impl Write for Truncated {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remaining = MAX_MESSAGE_LEN - self.0.len();
+        if s.len() <= remaining {
+            self.0.push_str(s);
+            return Ok(());
+        }
+
+        let mut end = remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.0.push_str(&s[..end]);
+        self.0.push_str("...");
+        Err(fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    struct Panicking;
+
+    impl fmt::Display for Panicking {
+        fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+            panic!("Display implementation panicked")
+        }
+    }
+
+    #[test]
+    fn fallback_entries() {
+        let long = "a".repeat(MAX_MESSAGE_LEN * 2);
+        let entry = plain_entry(
+            &log::Record::builder()
+                .args(format_args!("{}", long))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+        );
+        assert_eq!(
+            entry,
+            format!("WARNING my_app: {}...", &long[..MAX_MESSAGE_LEN])
+        );
+
+        let entry = plain_entry(
+            &log::Record::builder()
+                .args(format_args!("{}", Panicking))
+                .level(Level::Error)
+                .target("my_app")
+                .build(),
+        );
+        assert_eq!(entry, "ERROR my_app: <unformattable message>");
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use log::kv;
+
+use {
+    crate::{cardinality, labels::RECORD_LABEL_PREFIX, severity::SEVERITY_FIELD},
+    serde_json::{json, Value},
+};
+
+// Naming convention applied to custom field keys
+static KEY_CASE: RwLock<KeyCase> = RwLock::new(KeyCase::Preserve);
+
+// Expand dotted keys into nested objects
+static NESTED_KEYS: AtomicBool = AtomicBool::new(false);
+
+// How float custom fields are formatted
+static FLOAT_FORMAT: RwLock<FloatFormat> = RwLock::new(FloatFormat {
+    max_decimals: None,
+    non_finite: NonFinite::String,
+});
+
+/// How float custom fields are formatted, see [`set_float_format`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FloatFormat {
+    /// Round floats to this number of decimal places, unlimited if `None`
+    pub max_decimals: Option<u32>,
+
+    /// How `NaN` and infinite values are emitted
+    pub non_finite: NonFinite,
+}
+
+/// How `NaN` and infinite floats are emitted, they aren't valid JSON numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFinite {
+    /// Emitted as the strings `NaN`, `inf` and `-inf`, this is the default
+    #[default]
+    String,
+
+    /// Emitted as `null`
+    Null,
+}
+
+/// Naming convention applied to custom field keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCase {
+    /// Keys are emitted as written at the call site, this is the default
+    Preserve,
+
+    /// Keys are converted to camelCase, e.g. `request_id` becomes `requestId`
+    Camel,
+
+    /// Keys are converted to snake_case, e.g. `requestId` becomes `request_id`
+    Snake,
+}
+
+/// Normalize custom field keys to a single naming convention.
+///
+/// Dots are kept as is, each dot-separated segment is converted on its own.
+/// Requires the `customfields` feature.
+/// ## Usage
+/// ```rust
+/// use stackdriver_logger::KeyCase;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_key_case(KeyCase::Camel);
+/// ```
+pub fn set_key_case(case: KeyCase) {
+    if let Ok(mut current) = KEY_CASE.write() {
+        *current = case;
+    }
+}
+
+/// Expand custom field keys containing dots into nested JSON objects.
+///
+/// With this enabled, `http.status` and `http.method` are both emitted under a single
+/// `http` object, following OpenTelemetry-style attribute naming.
+/// Keys colliding with an existing non-object value are kept flat. Keys are expanded in
+/// lexicographic order, so with both `http` and `http.status`, `http` keeps its value and
+/// `http.status` stays flat.
+/// Requires the `customfields` feature.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_nested_keys(true);
+/// ```
+pub fn set_nested_keys(enabled: bool) {
+    NESTED_KEYS.store(enabled, Ordering::Relaxed);
+}
+
+/// Format float custom fields consistently, for log-based metrics extraction.
+///
+/// Rounding also applies to floats nested in arrays and objects. Requires the `customfields`
+/// feature.
+/// ## Usage
+/// ```rust
+/// use stackdriver_logger::{FloatFormat, NonFinite};
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_float_format(FloatFormat {
+///     max_decimals: Some(3),
+///     non_finite: NonFinite::Null,
+/// });
+/// ```
+pub fn set_float_format(format: FloatFormat) {
+    if let Ok(mut current) = FLOAT_FORMAT.write() {
+        *current = format;
+    }
+}
+
+/// Capture a serializable value as a custom field, emitted as nested JSON.
+///
+/// This is the same as the `:serde` capture of the `log` macros, usable wherever a
+/// `log::kv::Value` is expected. Requires the `customfields_serde` feature.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use serde_json::json;
+/// use stackdriver_logger::serde_field;
+///
+/// stackdriver_logger::init();
+///
+/// let order = json!({ "id": 42, "items": ["book"] });
+/// info!(order = serde_field(&order); "Order placed");
+/// ```
+#[cfg(feature = "customfields_serde")]
+pub fn serde_field<T: serde::Serialize + 'static>(value: &T) -> kv::Value<'_> {
+    kv::Value::from_serde(value)
+}
+
+/// Capture an error as a custom field, emitted with its cause chain.
+///
+/// This is the same as the `:err` capture of the `log` macros, usable wherever a
+/// `log::kv::Value` is expected. Requires the `customfields_error` feature.
+/// ## Usage
+/// ```rust
+/// use log::error;
+/// use stackdriver_logger::error_field;
+///
+/// stackdriver_logger::init();
+///
+/// let e = std::io::Error::new(std::io::ErrorKind::NotFound, "config.toml");
+/// error!(err = error_field(&e); "Could not load the configuration");
+/// ```
+#[cfg(feature = "customfields_error")]
+pub fn error_field<'v>(error: &'v (dyn std::error::Error + 'static)) -> kv::Value<'v> {
+    kv::Value::from_dyn_error(error)
+}
+
+// Wrap a Hashmap so we can implement log::kv traits for structured logging of custom fields
+// See https://cloud.google.com/logging/docs/view/overview#custom-fields
+pub(crate) struct CustomFields<'kvs>(HashMap<kv::Key<'kvs>, kv::Value<'kvs>>);
+
+impl<'kvs> CustomFields<'kvs> {
+    pub(crate) fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    pub(crate) fn inner(&self) -> &HashMap<kv::Key<'_>, kv::Value<'_>> {
+        &self.0
+    }
+}
+
+impl<'kvs> kv::VisitSource<'kvs> for CustomFields<'kvs> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key, value);
+        Ok(())
+    }
+}
+
+// Add the record's custom fields to a JSON payload
+pub(crate) fn insert_custom_fields(payload: &mut Value, record: &log::Record<'_>) {
+    let mut custom_fields = CustomFields::new();
+    if record.key_values().visit(&mut custom_fields).is_err() {
+        return;
+    }
+
+    let mut fields = BTreeMap::new();
+    for (key, val) in custom_fields.inner().iter() {
+        // Emitted as labels or as the severity instead
+        if key.as_str().starts_with(RECORD_LABEL_PREFIX) || key.as_str() == SEVERITY_FIELD {
+            continue;
+        }
+
+        let key = normalize_key(key.as_str());
+
+        #[cfg(feature = "customfields_error")]
+        let value = match val.to_borrowed_error() {
+            Some(error) => error_value(error),
+            None => field_value(val),
+        };
+
+        #[cfg(not(feature = "customfields_error"))]
+        let value = field_value(val);
+
+        // Only plain values count towards the cardinality limit
+        let value = match value {
+            Value::String(value) => match cardinality::guard(&key, &value) {
+                Some(value) => Value::String(value.into_owned()),
+                None => continue,
+            },
+            value => value,
+        };
+
+        fields.insert(key, value);
+    }
+
+    insert_fields(payload, fields, NESTED_KEYS.load(Ordering::Relaxed));
+}
+
+// Sorted keys make collisions between nested keys deterministic, see `set_nested_keys`
+fn insert_fields(payload: &mut Value, fields: BTreeMap<String, Value>, nested: bool) {
+    for (key, value) in fields {
+        if nested {
+            insert_nested(payload, &key, value);
+        } else {
+            payload[key] = value;
+        }
+    }
+}
+
+// Custom fields emitted as labels, without their prefix
+pub(crate) fn record_labels(record: &log::Record<'_>) -> Vec<(String, String)> {
+    let mut custom_fields = CustomFields::new();
+    if record.key_values().visit(&mut custom_fields).is_err() {
+        return Vec::new();
+    }
+
+    custom_fields
+        .inner()
+        .iter()
+        .filter_map(|(key, value)| {
+            let label = key.as_str().strip_prefix(RECORD_LABEL_PREFIX)?;
+            Some((label.to_owned(), value.to_string()))
+        })
+        .collect()
+}
+
+// Sequences, maps and structs captured through serde or sval are emitted as real JSON
+// arrays and objects
+#[cfg(feature = "customfields_serde")]
+fn field_value(value: &kv::Value<'_>) -> Value {
+    match serde_json::to_value(value) {
+        Ok(mut nested @ (Value::Array(_) | Value::Object(_))) => {
+            let format = float_format();
+            if let Some(decimals) = format.max_decimals {
+                round_nested(&mut nested, decimals);
+            }
+            nested
+        }
+        _ => primitive_value(value),
+    }
+}
+
+#[cfg(not(feature = "customfields_serde"))]
+fn field_value(value: &kv::Value<'_>) -> Value {
+    primitive_value(value)
+}
+
+// Numbers, booleans and null keep their JSON type, everything else is formatted
+fn primitive_value(value: &kv::Value<'_>) -> Value {
+    struct Primitive(Value);
+
+    impl<'v> kv::VisitValue<'v> for Primitive {
+        fn visit_any(&mut self, value: kv::Value<'_>) -> Result<(), kv::Error> {
+            self.0 = Value::String(value.to_string());
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> Result<(), kv::Error> {
+            self.0 = Value::Null;
+            Ok(())
+        }
+
+        fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+            self.0 = json!(value);
+            Ok(())
+        }
+
+        fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+            self.0 = json!(value);
+            Ok(())
+        }
+
+        fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+            self.0 = float_value(value, float_format());
+            Ok(())
+        }
+
+        fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+            self.0 = Value::Bool(value);
+            Ok(())
+        }
+    }
+
+    let mut primitive = Primitive(Value::Null);
+    match value.visit(&mut primitive) {
+        Ok(()) => primitive.0,
+        Err(_) => Value::String(value.to_string()),
+    }
+}
+
+fn float_format() -> FloatFormat {
+    FLOAT_FORMAT
+        .read()
+        .map(|format| *format)
+        .unwrap_or_default()
+}
+
+// Non-finite floats aren't valid JSON numbers
+fn float_value(value: f64, format: FloatFormat) -> Value {
+    let value = match format.max_decimals {
+        Some(decimals) => round(value, decimals),
+        None => value,
+    };
+
+    match serde_json::Number::from_f64(value) {
+        Some(number) => Value::Number(number),
+        None => match format.non_finite {
+            NonFinite::String => Value::String(value.to_string()),
+            NonFinite::Null => Value::Null,
+        },
+    }
+}
+
+// Values too large to be scaled are kept as is, they have no fraction anyway
+fn round(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals.min(i32::MAX as u32) as i32);
+    let rounded = (value * scale).round() / scale;
+    if rounded.is_finite() {
+        rounded
+    } else {
+        value
+    }
+}
+
+#[cfg(feature = "customfields_serde")]
+fn round_nested(value: &mut Value, decimals: u32) {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            if let Some(rounded) = number
+                .as_f64()
+                .and_then(|n| serde_json::Number::from_f64(round(n, decimals)))
+            {
+                *number = rounded;
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| round_nested(v, decimals)),
+        Value::Object(values) => values.values_mut().for_each(|v| round_nested(v, decimals)),
+        _ => {}
+    }
+}
+
+// Errors captured with `:err` are emitted with their cause chain
+#[cfg(feature = "customfields_error")]
+fn error_value(error: &(dyn std::error::Error + 'static)) -> Value {
+    let causes: Vec<String> = std::iter::successors(error.source(), |e| e.source())
+        .map(|e| e.to_string())
+        .collect();
+
+    json!({
+        "message": error.to_string(),
+        "causes": causes,
+    })
+}
+
+// Insert a value following a dotted path, creating intermediate objects as needed
+fn insert_nested(payload: &mut Value, key: &str, value: Value) {
+    let segments: Vec<&str> = key.split('.').collect();
+    let (last, parents) = match segments.split_last() {
+        Some((last, parents)) if !segments.iter().any(|s| s.is_empty()) => (last, parents),
+        _ => {
+            payload[key] = value;
+            return;
+        }
+    };
+
+    // Don't overwrite existing values, keep the key flat instead
+    let mut current = &*payload;
+    for segment in parents {
+        match current.get(segment) {
+            Some(v) if v.is_object() => current = v,
+            Some(Value::Null) | None => break,
+            Some(_) => {
+                payload[key] = value;
+                return;
+            }
+        }
+    }
+
+    let mut target = payload;
+    for segment in parents {
+        let entry = &mut target[*segment];
+        if entry.is_null() {
+            *entry = json!({});
+        }
+        target = entry;
+    }
+    target[*last] = value;
+}
+
+// Apply the configured naming convention to a custom field key
+fn normalize_key(key: &str) -> String {
+    let case = KEY_CASE.read().map(|c| *c).unwrap_or(KeyCase::Preserve);
+    convert_key(key, case)
+}
+
+fn convert_key(key: &str, case: KeyCase) -> String {
+    let convert: fn(&str) -> String = match case {
+        KeyCase::Preserve => return key.to_owned(),
+        KeyCase::Camel => to_camel_case,
+        KeyCase::Snake => to_snake_case,
+    };
+
+    key.split('.').map(convert).collect::<Vec<_>>().join(".")
+}
+
+fn to_camel_case(segment: &str) -> String {
+    let mut output = String::with_capacity(segment.len());
+    let mut upper_next = false;
+    for c in segment.chars() {
+        if c == '_' || c == '-' || c == ' ' {
+            upper_next = !output.is_empty();
+        } else if upper_next {
+            output.extend(c.to_uppercase());
+            upper_next = false;
+        } else if output.is_empty() {
+            output.extend(c.to_lowercase());
+        } else {
+            output.push(c);
+        }
+    }
+
+    output
+}
+
+fn to_snake_case(segment: &str) -> String {
+    let chars: Vec<char> = segment.chars().collect();
+    let mut output = String::with_capacity(segment.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '-' || *c == ' ' {
+            output.push('_');
+            continue;
+        }
+
+        // Start a new word on lowercase to uppercase transitions, and at the end of acronyms
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_lower)
+            {
+                output.push('_');
+            }
+        }
+        output.extend(c.to_lowercase());
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_conversion() {
+        assert_eq!(convert_key("request_id", KeyCase::Camel), "requestId");
+        assert_eq!(convert_key("user-agent", KeyCase::Camel), "userAgent");
+        assert_eq!(
+            convert_key("http.status_code", KeyCase::Camel),
+            "http.statusCode"
+        );
+        assert_eq!(convert_key("RequestId", KeyCase::Camel), "requestId");
+
+        assert_eq!(convert_key("requestId", KeyCase::Snake), "request_id");
+        assert_eq!(convert_key("user-agent", KeyCase::Snake), "user_agent");
+        assert_eq!(
+            convert_key("http.statusCode", KeyCase::Snake),
+            "http.status_code"
+        );
+        assert_eq!(convert_key("HTTPStatus", KeyCase::Snake), "http_status");
+
+        assert_eq!(convert_key("some_Key", KeyCase::Preserve), "some_Key");
+    }
+
+    #[test]
+    fn nested_keys() {
+        let mut payload = json!({ "message": "Info!" });
+        insert_nested(&mut payload, "http.status", json!("200"));
+        insert_nested(&mut payload, "http.method", json!("GET"));
+        insert_nested(&mut payload, "db.query.rows", json!("12"));
+        insert_nested(&mut payload, "message.extra", json!("flat"));
+        insert_nested(&mut payload, "trailing.", json!("flat"));
+
+        let expected = json!({
+            "message": "Info!",
+            "message.extra": "flat",
+            "trailing.": "flat",
+            "http": { "status": "200", "method": "GET" },
+            "db": { "query": { "rows": "12" } },
+        });
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn colliding_nested_keys() {
+        let fields = [
+            ("http.status".to_owned(), json!("200")),
+            ("http".to_owned(), json!("proxy")),
+            ("db.query".to_owned(), json!({ "rows": "12" })),
+            ("db.query.table".to_owned(), json!("users")),
+        ];
+
+        let mut payload = json!({});
+        insert_fields(&mut payload, fields.into_iter().collect(), true);
+        assert_eq!(
+            payload,
+            json!({
+                "http": "proxy",
+                "http.status": "200",
+                "db": { "query": { "rows": "12", "table": "users" } },
+            })
+        );
+    }
+
+    #[test]
+    fn label_fields() {
+        let fields = vec![
+            ("label.tier", kv::Value::from("free")),
+            ("user", kv::Value::from("alice")),
+        ];
+
+        let record = log::Record::builder()
+            .args(format_args!("Info!"))
+            .key_values(&fields)
+            .build();
+
+        let mut payload = json!({});
+        insert_custom_fields(&mut payload, &record);
+        assert_eq!(payload, json!({ "user": "alice" }));
+        assert_eq!(
+            record_labels(&record),
+            vec![("tier".to_owned(), "free".to_owned())]
+        );
+    }
+
+    #[test]
+    fn float_values() {
+        let format = FloatFormat {
+            max_decimals: Some(2),
+            non_finite: NonFinite::Null,
+        };
+        assert_eq!(float_value(1.0 / 3.0, format), json!(0.33));
+        assert_eq!(float_value(2.5e300, format), json!(2.5e300));
+        assert_eq!(float_value(f64::NAN, format), Value::Null);
+        assert_eq!(
+            float_value(f64::INFINITY, FloatFormat::default()),
+            json!("inf")
+        );
+    }
+
+    #[test]
+    fn primitive_values() {
+        let fields = vec![
+            ("count", kv::Value::from(5)),
+            ("ratio", kv::Value::from(0.5)),
+            ("cached", kv::Value::from(true)),
+            ("missing", kv::Value::null()),
+            ("user", kv::Value::from("alice")),
+        ];
+
+        let record = log::Record::builder()
+            .args(format_args!("Info!"))
+            .key_values(&fields)
+            .build();
+
+        let mut payload = json!({});
+        insert_custom_fields(&mut payload, &record);
+        assert_eq!(
+            payload,
+            json!({
+                "count": 5,
+                "ratio": 0.5,
+                "cached": true,
+                "missing": null,
+                "user": "alice",
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "customfields_serde")]
+    fn sequence_values() {
+        let tags = vec!["a", "b"];
+        let fields = vec![
+            ("tags", kv::Value::from_serde(&tags)),
+            ("count", kv::Value::from(3)),
+        ];
+
+        let record = log::Record::builder()
+            .args(format_args!("Info!"))
+            .key_values(&fields)
+            .build();
+
+        let mut payload = json!({});
+        insert_custom_fields(&mut payload, &record);
+        assert_eq!(payload, json!({ "tags": ["a", "b"], "count": 3 }));
+    }
+
+    #[test]
+    #[cfg(feature = "customfields_serde")]
+    fn nested_values() {
+        let mut request = std::collections::BTreeMap::new();
+        request.insert("method", "GET");
+        request.insert("path", "/health");
+        let fields = vec![("request", serde_field(&request))];
+
+        let record = log::Record::builder()
+            .args(format_args!("Info!"))
+            .key_values(&fields)
+            .build();
+
+        let mut payload = json!({});
+        insert_custom_fields(&mut payload, &record);
+        assert_eq!(
+            payload,
+            json!({ "request": { "method": "GET", "path": "/health" } })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "customfields_error")]
+    fn error_values() {
+        #[derive(Debug)]
+        struct ConfigError(std::io::Error);
+
+        impl std::fmt::Display for ConfigError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("Could not load the configuration")
+            }
+        }
+
+        impl std::error::Error for ConfigError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let error = ConfigError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "config.toml not found",
+        ));
+        let fields = vec![("err", kv::Value::from_dyn_error(&error))];
+
+        let record = log::Record::builder()
+            .args(format_args!("Error!"))
+            .key_values(&fields)
+            .build();
+
+        let mut payload = json!({});
+        insert_custom_fields(&mut payload, &record);
+        assert_eq!(
+            payload,
+            json!({
+                "err": {
+                    "message": "Could not load the configuration",
+                    "causes": ["config.toml not found"],
+                }
+            })
+        );
+    }
+}
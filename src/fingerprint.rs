@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Emit a fingerprint field with every entry
+static FINGERPRINTS: AtomicBool = AtomicBool::new(false);
+
+/// Emit a stable `fingerprint` field grouping similar entries.
+///
+/// The fingerprint is a hash of the message, with numbers masked, and of the location of the
+/// call site. Entries only differing by ids, counts or durations share a fingerprint, which
+/// makes grouping and deduplication easy in downstream analytics. Fingerprints are stable
+/// across processes and releases, as long as the call site doesn't move.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_fingerprints(true);
+/// ```
+pub fn set_fingerprints(enabled: bool) {
+    FINGERPRINTS.store(enabled, Ordering::Relaxed);
+}
+
+// Fingerprint of a record, when enabled
+pub(crate) fn record_fingerprint(record: &log::Record<'_>, message: &str) -> Option<String> {
+    if FINGERPRINTS.load(Ordering::Relaxed) {
+        Some(fingerprint(record, message))
+    } else {
+        None
+    }
+}
+
+// Hash of the normalized message and location
+pub(crate) fn fingerprint(record: &log::Record<'_>, message: &str) -> String {
+    let mut hash = Fnv::new();
+    hash.write(normalize(message).as_bytes());
+
+    #[cfg(not(feature = "nolocation"))]
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        hash.write(format!("\0{}:{}", file, line).as_bytes());
+    }
+
+    #[cfg(feature = "nolocation")]
+    hash.write(format!("\0{}", record.target()).as_bytes());
+
+    format!("{:016x}", hash.0)
+}
+
+// Mask numbers, so `took 12ms` and `took 250ms` are the same message
+fn normalize(message: &str) -> String {
+    let mut normalized = String::with_capacity(message.len());
+    let mut in_number = false;
+    for c in message.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                normalized.push('#');
+            }
+            in_number = true;
+        } else {
+            in_number = false;
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+// 64-bit FNV-1a, stable across processes unlike the std hasher
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_fingerprints() {
+        assert_eq!(normalize("Took 12ms for user 42"), "Took #ms for user #");
+
+        let record = |line| {
+            log::Record::builder()
+                .target("my_app")
+                .file(Some("src/main.rs"))
+                .line(Some(line))
+                .build()
+        };
+
+        let first = fingerprint(&record(10), "Took 12ms");
+        assert_eq!(first, fingerprint(&record(10), "Took 250ms"));
+        assert_ne!(first, fingerprint(&record(10), "Took 12s"));
+
+        #[cfg(not(feature = "nolocation"))]
+        assert_ne!(first, fingerprint(&record(11), "Took 12ms"));
+    }
+}
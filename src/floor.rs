@@ -0,0 +1,79 @@
+use std::{env, sync::RwLock};
+
+use log::{Level, LevelFilter};
+
+use crate::{audit, logger, resource};
+
+// Most verbose level emitted in the detected environment, `None` without a floor
+static FLOOR: RwLock<Option<LevelFilter>> = RwLock::new(None);
+
+// Set to `1` or `true` to allow verbose entries anyway, e.g. while debugging an incident
+const IGNORE_FLOOR_VAR: &str = "LOG_IGNORE_SEVERITY_FLOOR";
+
+/// Never emit entries less severe than `min_severity` in a given deployment environment,
+/// whatever `RUST_LOG` or filter overrides ask for.
+///
+/// This is a guardrail against deploying with verbose filters by accident. The environment is
+/// read from `DEPLOYMENT_ENVIRONMENT`, or from the `deployment.environment` attribute of
+/// `OTEL_RESOURCE_ATTRIBUTES`, when calling this function. The floor doesn't apply if
+/// `LOG_IGNORE_SEVERITY_FLOOR` is set to `1` or `true`. Entries about the logger itself are
+/// always emitted, a `NOTICE` entry reports the new floor.
+/// ## Usage
+/// ```rust
+/// use log::Level;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_severity_floor("prod", Level::Info);
+/// ```
+pub fn set_severity_floor(environment: &str, min_severity: Level) {
+    let ignored = env::var(IGNORE_FLOOR_VAR).is_ok_and(|v| v == "1" || v == "true");
+    let detected = resource::environment();
+    if let Some(level) = floor(detected.as_deref(), environment, min_severity, ignored) {
+        let before = audit::Snapshot::capture();
+        if let Ok(mut floor) = FLOOR.write() {
+            *floor = Some(level);
+        }
+        logger::refresh_max_level();
+        audit::config_changed("set_severity_floor", &before, &audit::Snapshot::capture());
+    }
+}
+
+// Floor to apply, if the environment matches and the floor isn't ignored
+fn floor(
+    detected: Option<&str>,
+    environment: &str,
+    min_severity: Level,
+    ignored: bool,
+) -> Option<LevelFilter> {
+    if ignored || !detected?.eq_ignore_ascii_case(environment) {
+        return None;
+    }
+    Some(min_severity.to_level_filter())
+}
+
+// Floor in effect, if any
+pub(crate) fn current() -> Option<LevelFilter> {
+    FLOOR.read().ok().and_then(|floor| *floor)
+}
+
+// Most verbose level allowed by the floor
+pub(crate) fn max_level() -> LevelFilter {
+    current().unwrap_or(LevelFilter::Trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_environment() {
+        let prod = Some("PROD");
+        assert_eq!(
+            floor(prod, "prod", Level::Info, false),
+            Some(LevelFilter::Info)
+        );
+        assert_eq!(floor(prod, "prod", Level::Info, true), None);
+        assert_eq!(floor(Some("staging"), "prod", Level::Info, false), None);
+        assert_eq!(floor(None, "prod", Level::Info, false), None);
+    }
+}
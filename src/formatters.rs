@@ -0,0 +1,75 @@
+use std::sync::RwLock;
+
+use serde_json::Value;
+
+// Reshape the default payload of a record
+type FormatFn = Box<dyn Fn(&log::Record<'_>, Value) -> Value + Send + Sync>;
+
+// Formatters registered per target
+static FORMATTERS: RwLock<Vec<(String, FormatFn)>> = RwLock::new(Vec::new());
+
+/// Register a custom formatter for records of a given target.
+///
+/// The formatter receives the record and the payload built by the default formatter,
+/// and returns the entry to emit. It applies to the target and its submodules, like
+/// `RUST_LOG` directives, the most specific target wins. Other targets keep the default format.
+/// Only JSON output is affected.
+/// ## Usage
+/// ```rust
+/// use serde_json::json;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_target_formatter("audit", |record, _default| {
+///     json!({
+///         "severity": "NOTICE",
+///         "message": record.args().to_string(),
+///         "audit": true,
+///     })
+/// });
+/// ```
+pub fn set_target_formatter<F>(target: &str, formatter: F)
+where
+    F: Fn(&log::Record<'_>, Value) -> Value + Send + Sync + 'static,
+{
+    if let Ok(mut formatters) = FORMATTERS.write() {
+        formatters.retain(|(t, _)| t != target);
+        formatters.push((target.to_owned(), Box::new(formatter)));
+    }
+}
+
+// Run the formatter registered for the record target, if any
+pub(crate) fn apply(record: &log::Record<'_>, payload: Value) -> Value {
+    let formatters = match FORMATTERS.read() {
+        Ok(formatters) => formatters,
+        Err(_) => return payload,
+    };
+
+    let formatter = formatters
+        .iter()
+        .filter(|(target, _)| matches_target(target, record.target()))
+        .max_by_key(|(target, _)| target.len());
+
+    match formatter {
+        Some((_, format)) => format(record, payload),
+        None => payload,
+    }
+}
+
+pub(crate) fn matches_target(target: &str, record_target: &str) -> bool {
+    record_target
+        .strip_prefix(target)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_matching() {
+        assert!(matches_target("audit", "audit"));
+        assert!(matches_target("my_app::http", "my_app::http::access"));
+        assert!(!matches_target("my_app::http", "my_app::https"));
+        assert!(!matches_target("audit", "my_app::audit"));
+    }
+}
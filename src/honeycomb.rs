@@ -0,0 +1,108 @@
+use serde_json::{Map, Value};
+
+// Stackdriver keys and their Honeycomb counterparts, after flattening
+const RENAMED_KEYS: [(&str, &str); 8] = [
+    ("eventTime", "timestamp"),
+    ("severity", "level"),
+    ("serviceContext.service", "service.name"),
+    ("serviceContext.version", "service.version"),
+    ("reportLocation.filePath", "code.filepath"),
+    ("reportLocation.lineNumber", "code.lineno"),
+    ("reportLocation.modulePath", "code.namespace"),
+    ("logging.googleapis.com/spanId", "trace.span_id"),
+];
+
+const LABELS_PREFIX: &str = "logging.googleapis.com/labels.";
+const TRACE_KEY: &str = "logging.googleapis.com/trace";
+
+// Turn a Stackdriver payload into a flat Honeycomb wide event
+pub(crate) fn encode(payload: Value) -> Value {
+    let mut flat = Map::new();
+    flatten(&mut flat, None, payload);
+
+    let mut event = Map::new();
+    for (key, value) in flat {
+        if value.is_null() {
+            continue;
+        }
+
+        let key = match RENAMED_KEYS.iter().find(|(from, _)| *from == key) {
+            Some((_, to)) => (*to).to_owned(),
+            None => match key.strip_prefix(LABELS_PREFIX) {
+                Some(label) => format!("labels.{}", label),
+                None => key,
+            },
+        };
+
+        let value = match key.as_str() {
+            // Only the trace id, without the project
+            TRACE_KEY => {
+                let trace = value.as_str().unwrap_or_default();
+                let id = trace.rsplit('/').next().unwrap_or(trace);
+                event.insert("trace.trace_id".to_owned(), Value::from(id));
+                continue;
+            }
+            // Honeycomb charts durations, they must be numbers
+            "duration_ms" => match value.as_str().and_then(|v| v.parse::<f64>().ok()) {
+                Some(duration) => Value::from(duration),
+                None => value,
+            },
+            _ => value,
+        };
+
+        event.insert(key, value);
+    }
+
+    Value::Object(event)
+}
+
+fn flatten(flat: &mut Map<String, Value>, prefix: Option<&str>, value: Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let key = match prefix {
+                    Some(prefix) => format!("{}.{}", prefix, key),
+                    None => key,
+                };
+                flatten(flat, Some(&key), value);
+            }
+        }
+        value => {
+            flat.insert(prefix.unwrap_or_default().to_owned(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn wide_events() {
+        let payload = json!({
+            "eventTime": "2024-03-01T12:00:00+00:00",
+            "severity": "INFO",
+            "message": "Request served",
+            "serviceContext": { "service": "api", "version": "1.2.0" },
+            "reportLocation": null,
+            "logging.googleapis.com/labels": { "module": "api::http" },
+            "logging.googleapis.com/trace": "projects/my-project/traces/4bf92f35",
+            "duration_ms": "12.5",
+            "http": { "status": "200" },
+        });
+
+        let expected = json!({
+            "timestamp": "2024-03-01T12:00:00+00:00",
+            "level": "INFO",
+            "message": "Request served",
+            "service.name": "api",
+            "service.version": "1.2.0",
+            "labels.module": "api::http",
+            "trace.trace_id": "4bf92f35",
+            "duration_ms": 12.5,
+            "http.status": "200",
+        });
+        assert_eq!(encode(payload), expected);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{sync::Arc, time::Duration};
+
+use serde_json::{json, Map, Value};
+
+use crate::{context, Context, ContextGuard};
+
+// Special field enabling the request views of Cloud Logging
+const HTTP_REQUEST_KEY: &str = "httpRequest";
+
+// Contexts are cloned for every scope, share the request
+pub(crate) type SharedHttpRequest = Arc<HttpRequest>;
+
+/// An HTTP request, emitted as the `httpRequest` field of the entries logged while it is
+/// entered, so they show up in the request views of Cloud Logging.
+///
+/// Only the fields that are set are emitted.
+/// ## Usage
+/// ```rust
+/// use std::time::Duration;
+/// use log::info;
+/// use stackdriver_logger::HttpRequest;
+///
+/// stackdriver_logger::init();
+///
+/// let request = HttpRequest {
+///     method: Some("GET".to_owned()),
+///     url: Some("https://example.com/users/42".to_owned()),
+///     status: Some(200),
+///     latency: Some(Duration::from_millis(35)),
+///     ..HttpRequest::default()
+/// };
+/// request.scope(|| info!("GET /users/42"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// Request method, e.g. `GET`
+    pub method: Option<String>,
+    /// Scheme, host, path and query of the request
+    pub url: Option<String>,
+    /// Size of the request in bytes, headers and body included
+    pub request_size: Option<u64>,
+    /// Response status code
+    pub status: Option<u16>,
+    /// Size of the response in bytes, headers and body included
+    pub response_size: Option<u64>,
+    /// User agent sent by the client
+    pub user_agent: Option<String>,
+    /// IP address of the client
+    pub remote_ip: Option<String>,
+    /// IP address of the server handling the request
+    pub server_ip: Option<String>,
+    /// Referer header of the request
+    pub referer: Option<String>,
+    /// Time between receiving the request and sending the response
+    pub latency: Option<Duration>,
+    /// Protocol of the request, e.g. `HTTP/1.1`
+    pub protocol: Option<String>,
+}
+
+impl HttpRequest {
+    /// Enter this request, within the current context, until the guard is dropped.
+    pub fn enter(&self) -> ContextGuard {
+        self.context().enter()
+    }
+
+    /// Run a closure with this request entered, within the current context.
+    pub fn scope<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.context().scope(f)
+    }
+
+    fn context(&self) -> Context {
+        Context::current().with_http_request(self.clone())
+    }
+
+    // LogEntry representation, 64 bits integers are strings and durations are in seconds
+    fn to_value(&self) -> Value {
+        let mut value = Map::new();
+        let mut insert = |key: &str, field: Option<Value>| {
+            if let Some(field) = field {
+                value.insert(key.to_owned(), field);
+            }
+        };
+
+        insert("requestMethod", self.method.as_ref().map(|m| json!(m)));
+        insert("requestUrl", self.url.as_ref().map(|u| json!(u)));
+        insert(
+            "requestSize",
+            self.request_size.map(|s| json!(s.to_string())),
+        );
+        insert("status", self.status.map(|s| json!(s)));
+        insert(
+            "responseSize",
+            self.response_size.map(|s| json!(s.to_string())),
+        );
+        insert("userAgent", self.user_agent.as_ref().map(|u| json!(u)));
+        insert("remoteIp", self.remote_ip.as_ref().map(|ip| json!(ip)));
+        insert("serverIp", self.server_ip.as_ref().map(|ip| json!(ip)));
+        insert("referer", self.referer.as_ref().map(|r| json!(r)));
+        insert(
+            "latency",
+            self.latency
+                .map(|l| json!(format!("{}.{:09}s", l.as_secs(), l.subsec_nanos()))),
+        );
+        insert("protocol", self.protocol.as_ref().map(|p| json!(p)));
+
+        Value::Object(value)
+    }
+}
+
+// Add the request of the current context, if any
+pub(crate) fn insert_http_request(payload: &mut Value) {
+    if let Some(request) = context::current_http_request() {
+        payload[HTTP_REQUEST_KEY] = request.to_value();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_fields() {
+        let request = HttpRequest {
+            method: Some("POST".to_owned()),
+            url: Some("https://example.com/upload".to_owned()),
+            request_size: Some(1024),
+            status: Some(201),
+            latency: Some(Duration::from_millis(1500)),
+            ..HttpRequest::default()
+        };
+
+        let mut payload = json!({});
+        request.scope(|| insert_http_request(&mut payload));
+        assert_eq!(
+            payload,
+            json!({
+                "httpRequest": {
+                    "requestMethod": "POST",
+                    "requestUrl": "https://example.com/upload",
+                    "requestSize": "1024",
+                    "status": 201,
+                    "latency": "1.500000000s",
+                }
+            })
+        );
+    }
+}
@@ -1,42 +1,156 @@
 #![doc = include_str!("../README.md")]
 #![forbid(unsafe_code)]
 
-use std::{env, fmt};
+use std::{env, sync::RwLock, time::SystemTime};
 
-use log::{Level, SetLoggerError};
+use log::LevelFilter;
 
-#[cfg(any(test, not(all(feature = "pretty_env_logger", debug_assertions))))]
-use serde_json::{json, Value};
+use {
+    log::Level,
+    serde_json::{json, Value},
+    std::{collections::BTreeMap, fmt},
+};
 
 #[cfg(feature = "cargo")]
 #[doc(hidden)]
 #[macro_use]
 pub mod macros;
 
+#[cfg(feature = "admin")]
+mod admin;
+mod audit;
+mod banner;
+mod bigquery;
+mod breaker;
+mod builder;
+mod caller;
+mod cardinality;
+mod child;
+mod clock;
+mod context;
+mod costs;
+#[cfg(all(unix, feature = "crashbuffer"))]
+mod crash;
+mod directives;
+mod error;
+mod fallback;
 #[cfg(feature = "customfields")]
-use log::kv;
+mod fields;
+mod fingerprint;
+mod floor;
+mod formatters;
+mod honeycomb;
+mod http;
+mod labels;
+mod limits;
+mod logger;
+mod lognames;
+mod operation;
+mod overhead;
+mod partitions;
+mod passthrough;
+mod pipeline;
+mod podinfo;
+mod record;
+mod replay;
+mod resource;
+mod selftest;
+mod severity;
+#[cfg(all(unix, feature = "signals"))]
+mod signals;
+mod source;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod summary;
+mod tasks;
+#[doc(hidden)]
+pub mod templated;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod timestamp;
+mod trace;
+mod tracked;
+mod trigger;
+mod writer;
 
+#[cfg(feature = "admin")]
+pub use admin::serve_admin;
+pub use banner::log_startup_banner;
+pub use breaker::set_error_circuit_breaker;
+pub use builder::{builder, Builder, Format, Target};
+#[cfg(feature = "caller")]
+pub use caller::set_caller_skip_list;
+pub use caller::set_source_location;
+pub use cardinality::{set_cardinality_limit, CardinalityAction};
+pub use child::{forward_child_output, ForwardedOutput};
+pub use context::{lazy_field, spawn_logged, with_attempt, Budget, Context, ContextGuard};
+pub use costs::set_daily_target_budget;
+#[cfg(all(unix, feature = "crashbuffer"))]
+pub use crash::{close_crash_buffer, recover_crash_buffer, set_crash_buffer};
+pub use error::InitError;
+#[cfg(feature = "customfields_error")]
+pub use fields::error_field;
+#[cfg(feature = "customfields_serde")]
+pub use fields::serde_field;
+#[cfg(all(
+    feature = "customfields",
+    feature = "pretty_env_logger",
+    debug_assertions
+))]
+use fields::CustomFields;
 #[cfg(feature = "customfields")]
-use std::collections::HashMap;
+pub use fields::{
+    set_float_format, set_key_case, set_nested_keys, FloatFormat, KeyCase, NonFinite,
+};
+
+pub use fingerprint::set_fingerprints;
+pub use floor::set_severity_floor;
+pub use formatters::set_target_formatter;
+pub use http::HttpRequest;
+pub use labels::{set_component_classifier, set_static_labels, set_target_label};
+pub use limits::set_max_entry_size;
+pub use lognames::set_log_name;
+pub use operation::Operation;
+pub use overhead::{logging_overhead, set_overhead_tracking, Overhead};
+pub use partitions::{register_partition, remove_partition, set_partition_field, Partition};
+pub use passthrough::set_json_passthrough;
+pub use pipeline::Pipeline;
+pub use podinfo::watch_pod_labels;
+pub use record::OwnedRecord;
+pub use replay::replay;
+pub use resource::set_resource_attributes;
+pub use selftest::{selftest, SelfTestReport, SinkReport};
+pub use severity::{set_severity_mapping, with_severity, Severity};
+#[cfg(all(unix, feature = "signals"))]
+pub use signals::watch_verbosity_signals;
+pub use source::set_source_link;
+#[cfg(feature = "stream")]
+pub use stream::{record_stream, RecordStream};
+pub use summary::log_shutdown_summary;
+pub use tasks::set_task_id_hook;
+pub use templates::{set_message_template, set_summary_key};
+pub use timestamp::{TimestampFormat, TimestampPrecision};
+pub use trace::{set_trace_project, set_trace_sampled_verbosity, TraceContext};
+pub use tracked::{
+    debug_tracked, error_tracked, info_tracked, log_tracked, trace_tracked, warn_tracked,
+};
+pub use trigger::watch_verbosity_trigger;
+pub use writer::{Batch, WorkerGuard};
 
 // Wrap Level from the log crate so we can implement standard traits for it
 struct LogLevel(Level);
 
-// Wrap a Hashmap so we can implement log::kv traits for structured logging of custom fields
-// See https://cloud.google.com/logging/docs/view/overview#custom-fields
-#[cfg(feature = "customfields")]
-struct CustomFields<'kvs>(HashMap<kv::Key<'kvs>, kv::Value<'kvs>>);
+// Reported when no service name is available
+const UNKNOWN_SERVICE: &str = "unknown_service";
 
-#[cfg(feature = "customfields")]
-impl<'kvs> CustomFields<'kvs> {
-    fn new() -> Self {
-        Self(HashMap::new())
-    }
+// Service context reported with every entry, can be updated at runtime
+static SERVICE: RwLock<Option<Service>> = RwLock::new(None);
 
-    fn inner(&self) -> &HashMap<kv::Key, kv::Value> {
-        &self.0
-    }
-}
+// Resolves the service context lazily, until it succeeds
+type ServiceResolver = Box<dyn Fn() -> Option<Service> + Send + Sync>;
+static SERVICE_RESOLVER: RwLock<Option<ServiceResolver>> = RwLock::new(None);
 
 /// Parameters expected by the logger, used for manual initialization.
 #[derive(Clone)]
@@ -66,6 +180,91 @@ impl Service {
     }
 }
 
+/// Update the service version reported in `serviceContext` at runtime.
+///
+/// This is useful for long-lived processes that switch behavior during a canary rollout,
+/// so errors are attributed to the version actually running.
+/// If the logger was initialized without service information, the service name is
+/// reported as `unknown_service`.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_service_version("2.4.0-canary");
+/// ```
+pub fn set_service_version<V: Into<String>>(version: V) {
+    let version = version.into();
+    if let Ok(mut service) = SERVICE.write() {
+        match service.as_mut() {
+            Some(service) => service.version = version,
+            None => {
+                *service = Some(Service {
+                    name: UNKNOWN_SERVICE.to_owned(),
+                    version,
+                })
+            }
+        }
+    }
+}
+
+/// Resolve the service context lazily, when the first entry is emitted.
+///
+/// This is useful when the service information isn't available at `init()` time, e.g. before
+/// configuration is loaded or the metadata server responds. The resolver is called for each
+/// entry until it returns `Some(Service)`, then never again. It is ignored if the service
+/// context is already known.
+/// ## Usage
+/// ```rust
+/// use stackdriver_logger::Service;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_service_resolver(|| {
+///     Some(Service {
+///         name: std::env::var("APP_NAME").ok()?,
+///         version: std::env::var("APP_VERSION").ok()?,
+///     })
+/// });
+/// ```
+pub fn set_service_resolver<F>(resolver: F)
+where
+    F: Fn() -> Option<Service> + Send + Sync + 'static,
+{
+    if let Ok(mut current) = SERVICE_RESOLVER.write() {
+        *current = Some(Box::new(resolver));
+    }
+}
+
+// Service context to report, resolving it if needed
+fn current_service() -> Option<Service> {
+    resolve_service(&SERVICE, &SERVICE_RESOLVER)
+}
+
+// Known service, or the one returned by the resolver which is dropped once it succeeds
+fn resolve_service(
+    service: &RwLock<Option<Service>>,
+    resolver: &RwLock<Option<ServiceResolver>>,
+) -> Option<Service> {
+    if let Some(service) = service.read().ok().and_then(|s| s.clone()) {
+        return Some(service);
+    }
+
+    // Take the resolver out while it runs, in case it logs something itself
+    let resolve = resolver.write().ok().and_then(|mut r| r.take())?;
+    match resolve() {
+        Some(resolved) => {
+            if let Ok(mut current) = service.write() {
+                *current = Some(resolved.clone());
+            }
+            Some(resolved)
+        }
+        None => {
+            if let Ok(mut current) = resolver.write() {
+                current.get_or_insert(resolve);
+            }
+            None
+        }
+    }
+}
+
 /// Basic initializer, expects SERVICE_NAME and SERVICE_VERSION env variables
 /// to be defined, otherwise you won't have much context available in Stackdriver.
 /// ## Usage
@@ -76,7 +275,7 @@ impl Service {
 /// info!("Make sur you don't forget the env variables !");
 /// ```
 pub fn init() {
-    try_init(None, true).expect("Could not initialize stackdriver_logger");
+    try_init().expect("Could not initialize stackdriver_logger");
 }
 
 /// Initialize the logger manually.
@@ -103,91 +302,444 @@ pub fn init() {
 /// info!("Make sur you don't forget the env variables !");
 /// ```
 pub fn init_with(service: Option<Service>, report_location: bool) {
-    try_init(service, report_location).expect("Could not initialize stackdriver_logger");
+    try_init_with(service, report_location).expect("Could not initialize stackdriver_logger");
 }
 
-// Initialize the logger, defaults to pretty_env_logger in debug mode
-// Allow unused variables for convenience when toggling feature flags
-#[allow(unused_variables)]
-pub(crate) fn try_init(
-    service: Option<Service>,
-    report_location: bool,
-) -> Result<(), SetLoggerError> {
-    #[cfg(all(feature = "pretty_env_logger", debug_assertions))]
-    {
-        #[cfg(feature = "customfields")]
-        {
-            use std::io::Write;
-            let mut builder = env_logger::Builder::new();
-            builder.format(move |f, record| writeln!(f, "{}", format_record_pretty(record)));
-        }
+/// Initializer with the recommended settings for Cloud Run.
+///
+/// - Warnings and errors are written to standard error, other entries to standard output
+/// - The location is reported as `logging.googleapis.com/sourceLocation`
+/// - The service is read from `SERVICE_NAME` and `SERVICE_VERSION`, or from the `K_SERVICE`
+///   and `K_REVISION` variables set by Cloud Run
+/// - Trace correlation is enabled when `GOOGLE_CLOUD_PROJECT` is set, feed the incoming
+///   `X-Cloud-Trace-Context` header to `TraceContext::from_cloud_trace_header`
+///
+/// Use `Builder::cloud_run` to change any of these.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::TraceContext;
+///
+/// stackdriver_logger::init_cloud_run();
+///
+/// let header = "105445aa7843bc8bf206b12000100000/1;o=1";
+/// let context = match TraceContext::from_cloud_trace_header(header) {
+///     Some(trace) => trace.context(),
+///     None => stackdriver_logger::Context::current(),
+/// };
+/// context.scope(|| info!("Handling request"));
+/// ```
+pub fn init_cloud_run() {
+    try_init_cloud_run().expect("Could not initialize stackdriver_logger");
+}
+
+/// Initializer with the recommended settings for GKE.
+///
+/// - Entries are written to standard output, where the GKE logging agent collects them
+/// - The timestamp is written under `time`, which the agent uses as the entry timestamp
+/// - The location is reported as `logging.googleapis.com/sourceLocation`
+/// - The pod name and namespace are reported under `resource`, see `set_resource_attributes`
+/// - The service is read from `SERVICE_NAME` and `SERVICE_VERSION`
+/// - Trace correlation is enabled when `GOOGLE_CLOUD_PROJECT` is set
+///
+/// Use `Builder::gke` to change any of these.
+/// ## Usage
+/// ```rust
+/// use log::info;
+///
+/// stackdriver_logger::init_gke();
+/// info!("Collected by the GKE logging agent");
+/// ```
+pub fn init_gke() {
+    try_init_gke().expect("Could not initialize stackdriver_logger");
+}
 
-        pretty_env_logger::try_init()
+/// Install a bare-bones logger early, before the configuration is loaded.
+///
+/// No service information is reported and neither is the location. Entries are emitted
+/// right away, e.g. by a configuration loader, then `upgrade` switches to the fully
+/// configured logger once the service information is known.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::Service;
+///
+/// stackdriver_logger::init_minimal();
+/// info!("Loading configuration");
+///
+/// let service = Service {
+///     name: "My Service".to_owned(),
+///     version: "2.3.1".to_owned(),
+/// };
+/// stackdriver_logger::upgrade(Some(service), true);
+/// info!("We're all set here !");
+/// ```
+pub fn init_minimal() {
+    try_init_minimal().expect("Could not initialize stackdriver_logger");
+}
+
+/// Switch to a fully configured logger, see `init_minimal`.
+///
+/// The change is atomic, entries are emitted either with the previous configuration or with
+/// the new one, in order. `RUST_LOG` is read again. If no logger was installed yet,
+/// this behaves like `init_with`. Use `Builder::upgrade` for more options.
+pub fn upgrade(service: Option<Service>, report_location: bool) {
+    let mut builder = builder().report_location(report_location);
+    builder.service = service;
+    builder.upgrade();
+}
+
+/// Fallible version of `init`.
+/// ## Usage
+/// ```rust
+/// if let Err(e) = stackdriver_logger::try_init() {
+///     eprintln!("Logging is not available: {}", e);
+/// }
+/// ```
+pub fn try_init() -> Result<(), InitError> {
+    try_init_with(None, true)
+}
+
+/// Fallible version of `init_with`.
+pub fn try_init_with(service: Option<Service>, report_location: bool) -> Result<(), InitError> {
+    let mut builder = builder().report_location(report_location);
+    builder.service = service;
+    builder.try_init()
+}
+
+/// Fallible version of `init_cloud_run`.
+pub fn try_init_cloud_run() -> Result<(), InitError> {
+    Builder::cloud_run().try_init()
+}
+
+/// Fallible version of `init_gke`.
+pub fn try_init_gke() -> Result<(), InitError> {
+    Builder::gke().try_init()
+}
+
+/// Fallible version of `init_minimal`.
+pub fn try_init_minimal() -> Result<(), InitError> {
+    try_init_with(None, false)
+}
+
+// Build the loggers, defaults to pretty_env_logger in debug mode
+fn build_loggers(config: &Builder) -> logger::Loggers {
+    let filters = match &config.filters {
+        Some(filters) => filters.clone(),
+        None => env::var("RUST_LOG").unwrap_or_default(),
+    };
+
+    // pretty_env_logger can't write to custom writers
+    #[cfg(all(feature = "pretty_env_logger", debug_assertions))]
+    if config.writer.is_none() {
+        return pretty_loggers(config, filters);
     }
 
-    #[cfg(not(all(feature = "pretty_env_logger", debug_assertions)))]
+    json_loggers(config, filters)
+}
+
+#[cfg(all(feature = "pretty_env_logger", debug_assertions))]
+fn pretty_loggers(config: &Builder, filters: String) -> logger::Loggers {
+    #[cfg(feature = "customfields")]
     {
         use std::io::Write;
         let mut builder = env_logger::Builder::new();
-        builder.format(move |f, record| {
-            writeln!(
-                f,
-                "{}",
-                format_record(record, service.as_ref(), report_location)
-            )
-        });
+        builder.format(move |f, record| writeln!(f, "{}", format_record_pretty(record)));
+    }
 
-        if let Ok(s) = ::std::env::var("RUST_LOG") {
-            builder.parse_filters(&s);
+    use pretty_env_logger::env_logger::Target as PrettyTarget;
+    let env_filters = directives::split(&filters).0;
+    let local_timestamps = config.local_timestamps;
+    let pretty_logger = |stream, filters: Option<&str>| {
+        let mut builder = pretty_env_logger::formatted_builder();
+        if local_timestamps {
+            builder.format(format_record_local_time);
         }
+        builder.target(match stream {
+            Target::Stdout => PrettyTarget::Stdout,
+            _ => PrettyTarget::Stderr,
+        });
+        match filters {
+            Some(filters) => builder.parse_filters(filters),
+            None => builder.filter_level(LevelFilter::Trace),
+        };
+        builder.build()
+    };
 
-        builder.try_init()
+    logger::Loggers {
+        default: logger::routed(config.target, |stream| {
+            pretty_logger(stream, Some(&env_filters))
+        }),
+        default_level: logger::filters_level(&env_filters),
+        default_filters: filters,
+        verbose: logger::routed(config.target, |stream| pretty_logger(stream, None)),
+        writers: Vec::new(),
+        workers: Vec::new(),
+        sinks: logger::stream_sinks(config.target, None, None),
+        pretty: true,
     }
 }
 
-// Format log level for Stackdriver
-impl fmt::Display for LogLevel {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(match self {
-            LogLevel(Level::Error) => "ERROR",
-            LogLevel(Level::Warn) => "WARNING",
-            LogLevel(Level::Info) => "INFO",
+// Same layout as pretty_env_logger, with a local timestamp
+#[cfg(all(feature = "pretty_env_logger", debug_assertions))]
+fn format_record_local_time(
+    f: &mut pretty_env_logger::env_logger::fmt::Formatter,
+    record: &log::Record<'_>,
+) -> std::io::Result<()> {
+    use pretty_env_logger::env_logger::fmt::Color;
+    use std::{
+        io::Write,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
 
-            // Debug and Trace are caught here. Stackdriver doesn't have Trace, we map it to Debug instead
-            LogLevel(_) => "DEBUG",
-        })
+    // Targets are padded to the longest one seen so far
+    static MAX_TARGET_WIDTH: AtomicUsize = AtomicUsize::new(0);
+    let width = MAX_TARGET_WIDTH
+        .fetch_max(record.target().len(), Ordering::Relaxed)
+        .max(record.target().len());
+
+    let mut level_style = f.style();
+    let level = match record.level() {
+        Level::Trace => level_style.set_color(Color::Magenta).value("TRACE"),
+        Level::Debug => level_style.set_color(Color::Blue).value("DEBUG"),
+        Level::Info => level_style.set_color(Color::Green).value("INFO "),
+        Level::Warn => level_style.set_color(Color::Yellow).value("WARN "),
+        Level::Error => level_style.set_color(Color::Red).value("ERROR"),
+    };
+
+    let mut target_style = f.style();
+    let target =
+        target_style
+            .set_bold(true)
+            .value(format!("{: <width$}", record.target(), width = width));
+
+    writeln!(
+        f,
+        " {} {} {} > {}",
+        clock::local(timestamp::record_time()),
+        level,
+        target,
+        record.args(),
+    )
+}
+
+// Color the severity of a JSON entry, env_logger only keeps colors when writing to a terminal
+#[cfg(feature = "termcolor")]
+fn write_colored_entry(
+    f: &mut env_logger::fmt::Formatter,
+    level: Level,
+    entry: &str,
+) -> std::io::Result<()> {
+    use env_logger::fmt::Color;
+    use std::io::Write;
+
+    const SEVERITY_KEY: &str = "\"severity\":";
+    let (before, severity, after) = match entry.find(SEVERITY_KEY).and_then(|start| {
+        let value = start + SEVERITY_KEY.len();
+        let end = value + 1 + entry[value + 1..].find('"')? + 1;
+        Some((&entry[..value], &entry[value..end], &entry[end..]))
+    }) {
+        Some(parts) => parts,
+        None => return writeln!(f, "{}", entry),
+    };
+
+    let mut style = f.style();
+    style.set_color(match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::Green,
+        Level::Debug => Color::Blue,
+        Level::Trace => Color::Magenta,
+    });
+    writeln!(f, "{}{}{}", before, style.value(severity), after)
+}
+
+#[cfg(not(feature = "termcolor"))]
+fn write_colored_entry(
+    f: &mut env_logger::fmt::Formatter,
+    _level: Level,
+    entry: &str,
+) -> std::io::Result<()> {
+    use std::io::Write;
+    writeln!(f, "{}", entry)
+}
+
+fn json_loggers(config: &Builder, filters: String) -> logger::Loggers {
+    use std::{
+        io::{self, Write},
+        panic::{self, AssertUnwindSafe},
+    };
+    use writer::{Batched, NonBlocking, SharedWriter};
+    let mut pipeline = Pipeline::new()
+        .report_location(config.report_location)
+        .format(config.format)
+        .timestamp_format(config.timestamp_format)
+        .timestamp_precision(config.timestamp_precision);
+    if let Some(key) = &config.timestamp_key {
+        pipeline = pipeline.timestamp_key(key);
+    }
+
+    // A custom writer takes precedence over the target
+    let target = match config.writer {
+        Some(_) => Target::Stdout,
+        None => config.target,
+    };
+
+    // Add the batching and non-blocking layers to a writer
+    let mut workers = Vec::new();
+    let mut layered = |writer: SharedWriter| {
+        let writer = match config.batch {
+            Some(batch) => SharedWriter::new(Batched::new(writer, batch)),
+            None => writer,
+        };
+        if !config.non_blocking {
+            return writer;
+        }
+        match NonBlocking::spawn(writer.clone()) {
+            Ok((non_blocking, worker)) => {
+                workers.push(worker);
+                SharedWriter::new(non_blocking)
+            }
+            Err(_) => writer,
+        }
+    };
+
+    // Writers for each stream, `None` writes to the stream directly
+    let (mut stdout, mut stderr) = (None, None);
+    match &config.writer {
+        Some(writer) => {
+            stdout = Some(layered(writer.clone()));
+            stderr = stdout.clone();
+        }
+        None if config.non_blocking || config.batch.is_some() => {
+            if target != Target::Stderr {
+                stdout = Some(layered(SharedWriter::new(io::stdout())));
+            }
+            if target != Target::Stdout {
+                stderr = Some(layered(SharedWriter::new(io::stderr())));
+            }
+        }
+        None => {}
+    }
+
+    let json_logger = |stream, filters: Option<&str>| {
+        let mut builder = env_logger::Builder::new();
+        let writer = match stream {
+            Target::Stdout => &stdout,
+            _ => &stderr,
+        };
+        builder.target(match (writer, stream) {
+            (Some(writer), _) => env_logger::Target::Pipe(Box::new(writer.clone())),
+            (None, Target::Stdout) => env_logger::Target::Stdout,
+            (None, _) => env_logger::Target::Stderr,
+        });
+        let pipeline = pipeline.clone();
+        let color_severity = config.color_severity;
+        builder.format(move |f, record| {
+            if let Some(entry) = replay::raw_entry() {
+                return writeln!(f, "{}", entry);
+            }
+
+            // Don't lose the event if a field or the message can't be formatted
+            let entry = panic::catch_unwind(AssertUnwindSafe(|| {
+                limits::encode(pipeline.process(record))
+            }));
+
+            match entry {
+                Ok(entry) => {
+                    costs::record(record.target(), entry.len() + 1);
+                    #[cfg(all(unix, feature = "crashbuffer"))]
+                    crash::record(&entry);
+                    match partitions::writer(record) {
+                        Some(mut writer) => writeln!(writer, "{}", entry),
+                        None if color_severity => write_colored_entry(f, record.level(), &entry),
+                        None => writeln!(f, "{}", entry),
+                    }
+                }
+                Err(_) => writeln!(f, "{}", fallback::plain_entry(record)),
+            }
+        });
+        match filters {
+            Some(filters) => builder.parse_filters(filters),
+            None => builder.filter_level(LevelFilter::Trace),
+        };
+        builder.build()
+    };
+
+    let env_filters = directives::split(&filters).0;
+
+    logger::Loggers {
+        default: logger::routed(target, |stream| json_logger(stream, Some(&env_filters))),
+        default_level: logger::filters_level(&env_filters),
+        default_filters: filters,
+        verbose: logger::routed(target, |stream| json_logger(stream, None)),
+        sinks: match config.writer {
+            Some(_) => vec![logger::Sink {
+                name: "writer",
+                writer: stdout.clone(),
+            }],
+            None => logger::stream_sinks(target, stdout.clone(), stderr.clone()),
+        },
+        writers: stdout.into_iter().chain(stderr).collect(),
+        workers,
+        pretty: false,
     }
 }
 
-#[cfg(feature = "customfields")]
-impl<'kvs> kv::Visitor<'kvs> for CustomFields<'kvs> {
-    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
-        self.0.insert(key, value);
-        Ok(())
+// Format log level for Stackdriver, see `set_severity_mapping`
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(severity::mapped(self.0).as_str())
     }
 }
 
 // Message structure is documented here: https://cloud.google.com/error-reporting/docs/formatting-error-messages
-#[cfg(any(test, not(all(feature = "pretty_env_logger", debug_assertions))))]
+#[cfg_attr(feature = "nolocation", allow(unused_variables))]
 fn format_record(
     record: &log::Record<'_>,
+    time: SystemTime,
     service: Option<&Service>,
     report_location: bool,
+    timestamp_key: &str,
 ) -> Value {
+    #[cfg(not(feature = "nolocation"))]
+    let location = caller::locate(record);
+
+    // Messages may be rendered through a template
+    let message = templates::render_message(record);
+    let (message, detail) = templates::split_detail(record, message);
+    let fingerprint = fingerprint::record_fingerprint(record, &message);
+
+    // Error messages also have a pseudo stack trace
+    #[cfg(not(feature = "nolocation"))]
+    let message = match record.level() {
+        Level::Error => format!(
+            "{} \n at {}:{}",
+            message,
+            location.file.as_deref().unwrap_or("unknown_file"),
+            location.line.unwrap_or(0)
+        ),
+        _ => message,
+    };
+
+    #[cfg(not(feature = "nolocation"))]
+    let report_location = if report_location {
+        json!({
+            "filePath": location.file,
+            "modulePath": location.module_path,
+            "lineNumber": location.line,
+        })
+    } else {
+        Value::Null
+    };
+
+    #[cfg(feature = "nolocation")]
+    let report_location = Value::Null;
+
     let json_payload = json!({
-        "eventTime": chrono::Utc::now().to_rfc3339(),
-        "severity": LogLevel(record.level()).to_string(),
-
-        // Error messages also have a pseudo stack trace
-        "message": match record.level() {
-            Level::Error => format!(
-                "{} \n at {}:{}",
-                record.args(),
-                record.file().unwrap_or("unknown_file"),
-                record.line().unwrap_or(0)
-            ),
-            _ => format!("{}", record.args()),
-        },
+        "eventTime": clock::to_rfc3339(time),
+        "severity": severity::severity(record),
+        "message": message,
 
         // Service context may or may not be defined
         "serviceContext": service.map(|s| json!({
@@ -195,35 +747,139 @@ fn format_record(
                 "version": s.version
             }))
             .unwrap_or_else(|| json!({
-                "service": "unknown_service"
+                "service": UNKNOWN_SERVICE
             })),
 
         // Report location may or may not be available
-        "reportLocation": if report_location {
-            json!({
-                "filePath": record.file(),
-                "modulePath": record.module_path(),
-                "lineNumber": record.line(),
-            })
-        } else {
-            Value::Null
-        }
+        "reportLocation": report_location
     });
 
-    #[cfg(not(feature = "customfields"))]
-    return json_payload;
+    // Full text of summarized messages
+    let json_payload = match detail {
+        Some(detail) => {
+            let mut json_payload = json_payload;
+            json_payload["detail"] = json!(detail);
+            json_payload
+        }
+        None => json_payload,
+    };
 
-    #[cfg(feature = "customfields")]
-    {
+    // Location for the Logs Explorer, when enabled
+    #[cfg(not(feature = "nolocation"))]
+    let json_payload = match location.source_location() {
+        Some(source_location) => {
+            let mut json_payload = json_payload;
+            json_payload["logging.googleapis.com/sourceLocation"] = source_location;
+            json_payload
+        }
+        None => json_payload,
+    };
+
+    // Link to the source code, when enabled
+    #[cfg(not(feature = "nolocation"))]
+    let json_payload = match source::reference(location.file.as_deref(), location.line) {
+        Some(reference) => {
+            let mut json_payload = json_payload;
+            json_payload["sourceReference"] = reference;
+            json_payload
+        }
+        None => json_payload,
+    };
+
+    // Grouping key of similar messages, when enabled
+    let json_payload = match fingerprint {
+        Some(fingerprint) => {
+            let mut json_payload = json_payload;
+            json_payload["fingerprint"] = json!(fingerprint);
+            json_payload
+        }
+        None => json_payload,
+    };
+
+    // Pre-structured messages, when enabled
+    let json_payload = {
         let mut json_payload = json_payload;
-        let mut custom_fields = CustomFields::new();
-        if record.key_values().visit(&mut custom_fields).is_ok() {
-            for (key, val) in custom_fields.inner().iter() {
-                json_payload[key.as_str()] = Value::String(val.to_string());
+        passthrough::merge_message(&mut json_payload, record, timestamp_key);
+        json_payload
+    };
+
+    // Labels are only emitted when at least one source is configured
+    let labels: BTreeMap<String, String> = labels::record_labels(record)
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = cardinality::guard(&key, &value)?.into_owned();
+            Some((key, value))
+        })
+        .collect();
+    let json_payload = if labels.is_empty() {
+        json_payload
+    } else {
+        let mut json_payload = json_payload;
+        json_payload["logging.googleapis.com/labels"] = json!(labels);
+        json_payload
+    };
+
+    // OpenTelemetry resource attributes, when enabled
+    let json_payload = match resource::resource(service) {
+        Some(resource) => {
+            let mut json_payload = json_payload;
+            json_payload["resource"] = resource;
+            json_payload
+        }
+        None => json_payload,
+    };
+
+    // Async task emitting the entry, when a hook is installed
+    let json_payload = match tasks::task_id() {
+        Some(task_id) => {
+            let mut json_payload = json_payload;
+            json_payload["taskId"] = json!(task_id);
+            json_payload
+        }
+        None => json_payload,
+    };
+
+    // Trace correlation when a trace project is configured, operation and request of the
+    // context, and log name of the target
+    let json_payload = {
+        let mut json_payload = json_payload;
+        trace::insert_trace(&mut json_payload, record);
+        operation::insert_operation(&mut json_payload);
+        http::insert_http_request(&mut json_payload);
+        lognames::insert_log_name(&mut json_payload, record);
+        json_payload
+    };
+
+    // Fields of the current context, record fields take precedence
+    let json_payload = {
+        let mut json_payload = json_payload;
+        for (key, value) in Context::current().fields() {
+            // Already applied to the severity
+            if key == severity::SEVERITY_FIELD {
+                continue;
+            }
+            if let Some(value) = cardinality::guard(&key, &value) {
+                json_payload[&key] = json!(value);
             }
         }
-        return json_payload;
-    }
+        json_payload
+    };
+
+    // Numeric fields of the shutdown summary
+    let json_payload = {
+        let mut json_payload = json_payload;
+        summary::insert_summary(&mut json_payload);
+        json_payload
+    };
+
+    #[cfg(feature = "customfields")]
+    let json_payload = {
+        let mut json_payload = json_payload;
+        fields::insert_custom_fields(&mut json_payload, record);
+        json_payload
+    };
+
+    json_payload
 }
 
 #[cfg(all(
@@ -253,8 +909,147 @@ fn format_record_pretty(record: &log::Record<'_>) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn lazy_service_resolution() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        // The global service is read by tests running in parallel
+        let service = RwLock::new(None);
+        let resolver: ServiceResolver = Box::new(|| match CALLS.fetch_add(1, Ordering::SeqCst) {
+            0 => None,
+            _ => Some(Service {
+                name: String::from("lazy"),
+                version: String::from("1.0.0"),
+            }),
+        });
+        let resolver = RwLock::new(Some(resolver));
+        let current_service = || resolve_service(&service, &resolver);
+
+        assert!(current_service().is_none());
+        assert_eq!(current_service().unwrap().name, "lazy");
+        assert_eq!(current_service().unwrap().version, "1.0.0");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn custom_writer() {
+        use {std::io, testing::Buffer};
+
+        let batch = Batch {
+            max_bytes: 64 * 1024,
+            interval: std::time::Duration::from_secs(60),
+        };
+        for (non_blocking, batched) in [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let buffer = Buffer::default();
+            let mut config = builder()
+                .writer(buffer.clone())
+                .non_blocking(non_blocking)
+                .report_location(false);
+            if batched {
+                config = config.batch(batch);
+            }
+            let loggers = build_loggers(&config);
+            loggers.verbose.log(
+                &log::Record::builder()
+                    .args(format_args!("Written to the buffer"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            if batched && !non_blocking {
+                assert!(buffer.is_empty());
+            }
+            for mut writer in loggers.writers.iter().cloned() {
+                io::Write::flush(&mut writer).unwrap();
+            }
+
+            let entry: Value = serde_json::from_str(&buffer.contents()).unwrap();
+            assert_eq!(entry["message"], "Written to the buffer");
+            assert_eq!(entry["severity"], "INFO");
+        }
+    }
+
+    #[test]
+    fn gke_entry() {
+        let _globals = testing::lock_globals();
+        let buffer = testing::Buffer::default();
+        let service = Service {
+            name: String::from("gke-app"),
+            version: String::from("1.0.0"),
+        };
+        let config = Builder::gke()
+            .service(service)
+            .writer(buffer.clone())
+            .report_location(false);
+        assert_eq!(config.target, Target::Stdout);
+
+        let loggers = build_loggers(&config);
+        config.apply();
+        loggers.verbose.log(
+            &log::Record::builder()
+                .args(format_args!("Collected by the agent"))
+                .level(Level::Warn)
+                .target("test_app")
+                .file(Some("my_file.rs"))
+                .line(Some(1337))
+                .module_path(Some("my_module"))
+                .build(),
+        );
+
+        // Restore the defaults before asserting, other tests rely on them
+        if let Ok(mut current) = SERVICE.write() {
+            *current = None;
+        }
+        set_source_location(false);
+        set_resource_attributes(false);
+
+        // Keys recognized by the GKE logging agent, the rest ends up in jsonPayload
+        let mut entry: Value = serde_json::from_str(&buffer.contents()).unwrap();
+        assert!(entry["time"].as_str().is_some());
+        assert!(entry.get("eventTime").is_none());
+        entry["time"] = json!("2019-09-28T04:00:00.000000000+00:00");
+        // Detected attributes depend on the environment
+        entry["resource"]
+            .as_object_mut()
+            .unwrap()
+            .retain(|key, _| key.starts_with("service."));
+
+        let expected = include_str!("../test_snapshots/gke_agent.json");
+        let mut expected: Value = serde_json::from_str(expected).unwrap();
+        if cfg!(feature = "nolocation") {
+            expected
+                .as_object_mut()
+                .unwrap()
+                .remove("logging.googleapis.com/sourceLocation");
+        }
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn decimal_formatting() {
+        let format = |args: fmt::Arguments<'_>| {
+            format_record(
+                &log::Record::builder().args(args).build(),
+                SystemTime::now(),
+                None,
+                false,
+                timestamp::DEFAULT_KEY,
+            )
+        };
+
+        let output = format(format_args!("Took {} seconds, {:.2}MB", 1.5, 1024.256));
+        assert_eq!(output["message"], "Took 1.5 seconds, 1024.26MB");
+
+        // Fractional seconds, e.g. `2019-09-28T04:00:00.000000000+00:00`
+        let timestamp = output["eventTime"].as_str().unwrap();
+        assert_eq!(&timestamp[19..20], ".");
+        assert_eq!(timestamp.len(), 35);
+    }
+
     #[test]
     fn info_formatter() {
+        let _globals = testing::lock_globals();
         let svc = Service {
             name: String::from("test"),
             version: String::from("0.0.0"),
@@ -269,7 +1064,13 @@ mod tests {
             .module_path(Some("my_module"))
             .build();
 
-        let mut output = format_record(&record, Some(&svc), false);
+        let mut output = format_record(
+            &record,
+            SystemTime::now(),
+            Some(&svc),
+            false,
+            timestamp::DEFAULT_KEY,
+        );
         let expected = include_str!("../test_snapshots/info_svc.json");
         let expected: Value = serde_json::from_str(expected).unwrap();
 
@@ -280,7 +1081,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "nolocation"))]
     fn error_formatter() {
+        let _globals = testing::lock_globals();
         let svc = Service {
             name: String::from("test"),
             version: String::from("0.0.0"),
@@ -295,14 +1098,26 @@ mod tests {
             .module_path(Some("my_module"))
             .build();
 
-        let mut output = format_record(&record, None, false);
+        let mut output = format_record(
+            &record,
+            SystemTime::now(),
+            None,
+            false,
+            timestamp::DEFAULT_KEY,
+        );
         let expected = include_str!("../test_snapshots/no_scv_no_loc.json");
         let expected: Value = serde_json::from_str(expected).unwrap();
         assert!(output["eventTime"].as_str().is_some());
         *output.get_mut("eventTime").unwrap() = json!("2019-09-28T04:00:00.000000000+00:00");
         assert_eq!(output, expected);
 
-        let mut output = format_record(&record, Some(&svc), true);
+        let mut output = format_record(
+            &record,
+            SystemTime::now(),
+            Some(&svc),
+            true,
+            timestamp::DEFAULT_KEY,
+        );
         let expected = include_str!("../test_snapshots/svc_and_loc.json");
         let expected: Value = serde_json::from_str(expected).unwrap();
         assert!(output["eventTime"].as_str().is_some());
@@ -310,9 +1125,76 @@ mod tests {
         assert_eq!(output, expected);
     }
 
+    #[test]
+    #[cfg(feature = "nolocation")]
+    fn no_location_formatter() {
+        let _globals = testing::lock_globals();
+        let svc = Service {
+            name: String::from("test"),
+            version: String::from("0.0.0"),
+        };
+
+        let record = log::Record::builder()
+            .args(format_args!("Error!"))
+            .level(Level::Error)
+            .target("test_app")
+            .file(Some("my_file.rs"))
+            .line(Some(1337))
+            .module_path(Some("my_module"))
+            .build();
+
+        let mut output = format_record(
+            &record,
+            SystemTime::now(),
+            Some(&svc),
+            true,
+            timestamp::DEFAULT_KEY,
+        );
+        let expected = include_str!("../test_snapshots/no_location.json");
+        let expected: Value = serde_json::from_str(expected).unwrap();
+        assert!(output["eventTime"].as_str().is_some());
+        *output.get_mut("eventTime").unwrap() = json!("2019-09-28T04:00:00.000000000+00:00");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn context_formatter() {
+        let _globals = testing::lock_globals();
+        let svc = Service {
+            name: String::from("test"),
+            version: String::from("0.0.0"),
+        };
+
+        let record = log::Record::builder()
+            .args(format_args!("Info!"))
+            .level(Level::Info)
+            .target("test_app")
+            .file(Some("my_file.rs"))
+            .line(Some(1337))
+            .module_path(Some("my_module"))
+            .build();
+
+        let context = Context::new().with_field("correlation_id", "4bf92f35");
+        let mut output = context.scope(|| {
+            format_record(
+                &record,
+                SystemTime::now(),
+                Some(&svc),
+                false,
+                timestamp::DEFAULT_KEY,
+            )
+        });
+        let expected = include_str!("../test_snapshots/context.json");
+        let expected: Value = serde_json::from_str(expected).unwrap();
+        assert!(output["eventTime"].as_str().is_some());
+        *output.get_mut("eventTime").unwrap() = json!("2019-09-28T04:00:00.000000000+00:00");
+        assert_eq!(output, expected);
+    }
+
     #[test]
     #[cfg(feature = "customfields")]
     fn custom_fields_formatter() {
+        let _globals = testing::lock_globals();
         let svc = Service {
             name: String::from("test"),
             version: String::from("0.0.0"),
@@ -329,10 +1211,16 @@ mod tests {
             .file(Some("my_file.rs"))
             .line(Some(1337))
             .module_path(Some("my_module"))
-            .key_values(&mut map)
+            .key_values(&map)
             .build();
 
-        let mut output = format_record(&record, Some(&svc), false);
+        let mut output = format_record(
+            &record,
+            SystemTime::now(),
+            Some(&svc),
+            false,
+            timestamp::DEFAULT_KEY,
+        );
         let expected = include_str!("../test_snapshots/custom_fields.json");
         let expected: Value = serde_json::from_str(expected).unwrap();
 
@@ -343,7 +1231,11 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "customfields")]
+    #[cfg(all(
+        feature = "pretty_env_logger",
+        feature = "customfields",
+        debug_assertions
+    ))]
     fn custom_fields_formatter_pretty() {
         let mut map = std::collections::HashMap::new();
         map.insert("a", "a value");
@@ -356,7 +1248,7 @@ mod tests {
             .file(Some("my_file.rs"))
             .line(Some(1337))
             .module_path(Some("my_module"))
-            .key_values(&mut map)
+            .key_values(&map)
             .build();
 
         let output = format_record_pretty(&record);
@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::Value;
+
+// Cloud Logging rejects entries over 256KiB
+const DEFAULT_MAX_ENTRY_SIZE: usize = 256 * 1024;
+
+// Max size of a formatted entry in bytes, 0 when disabled
+static MAX_ENTRY_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ENTRY_SIZE);
+
+// Set on entries that had to be truncated
+const TRUNCATED_KEY: &str = "truncated";
+
+// Never truncated, they are small and the entry is useless without them
+const RESERVED_KEYS: &[&str] = &[
+    "severity",
+    "eventTime",
+    "time",
+    "timestamp",
+    "timestampSeconds",
+    "timestampNanos",
+    "serviceContext",
+    "context",
+    "httpRequest",
+];
+
+// Kept when even reserved fields don't fit, along with a message replacing the entry
+const MINIMAL_KEYS: &[&str] = &[
+    "severity",
+    "eventTime",
+    "time",
+    "timestamp",
+    "timestampSeconds",
+    "timestampNanos",
+];
+
+/// Truncate entries larger than `max_bytes` once formatted, instead of having them rejected.
+///
+/// Cloud Logging rejects entries over 256KiB, which is the default limit. The longest strings
+/// of an oversized entry, the message or custom fields, are shortened until it fits, and a
+/// `truncated` field set to `true` is added. Timestamps, severity, service context, location
+/// and special `logging.googleapis.com/*` fields are left untouched. If that isn't enough,
+/// other fields are removed, largest first. If reserved fields alone are too large, only
+/// the timestamp and severity are kept, with a message telling the entry was dropped.
+/// Use `0` to disable the limit.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_max_entry_size(64 * 1024);
+/// ```
+pub fn set_max_entry_size(max_bytes: usize) {
+    MAX_ENTRY_SIZE.store(max_bytes, Ordering::Relaxed);
+}
+
+// Serialize a payload, truncated to the max entry size
+pub(crate) fn encode(payload: Value) -> String {
+    truncate(payload, MAX_ENTRY_SIZE.load(Ordering::Relaxed))
+}
+
+fn truncate(mut payload: Value, max_bytes: usize) -> String {
+    let mut entry = payload.to_string();
+    if max_bytes == 0 || entry.len() <= max_bytes {
+        return entry;
+    }
+
+    let original_len = entry.len();
+    let marker = format!(",\"{}\":true", TRUNCATED_KEY).len();
+    while entry.len() + marker > max_bytes {
+        let longest = match longest_string(&mut payload) {
+            Some(longest) if !longest.is_empty() => longest,
+            _ => break,
+        };
+
+        // Escaping only makes the serialized string longer, cutting raw bytes is enough
+        let excess = entry.len() + marker - max_bytes;
+        let mut len = longest.len().saturating_sub(excess);
+        while !longest.is_char_boundary(len) {
+            len -= 1;
+        }
+        longest.truncate(len);
+        entry = payload.to_string();
+    }
+
+    let fields = match payload.as_object_mut() {
+        Some(fields) => fields,
+        None => return entry,
+    };
+
+    // Only non-string values are left, remove the largest fields
+    while entry.len() + marker > max_bytes {
+        let largest = fields
+            .iter()
+            .filter(|(key, _)| truncatable(key))
+            .max_by_key(|(_, value)| value.to_string().len())
+            .map(|(key, _)| key.clone());
+        match largest {
+            Some(key) => fields.remove(&key),
+            None => break,
+        };
+        entry = serde_json::to_string(fields).unwrap_or_default();
+    }
+
+    // Reserved fields alone are too large, the entry can't be kept
+    if entry.len() + marker > max_bytes {
+        fields.retain(|key, _| MINIMAL_KEYS.contains(&key.as_str()));
+        fields.insert(
+            "message".to_owned(),
+            Value::String(format!(
+                "Entry of {} bytes dropped, over the {} bytes limit",
+                original_len, max_bytes
+            )),
+        );
+    }
+
+    fields.insert(TRUNCATED_KEY.to_owned(), Value::Bool(true));
+    payload.to_string()
+}
+
+// Whether a field may be truncated or removed
+fn truncatable(key: &str) -> bool {
+    !RESERVED_KEYS.contains(&key) && !key.starts_with("logging.googleapis.com/")
+}
+
+// Longest string of the entry that may be truncated
+fn longest_string(payload: &mut Value) -> Option<&mut String> {
+    let fields = payload.as_object_mut()?;
+    fields
+        .iter_mut()
+        .filter(|(key, _)| truncatable(key))
+        .filter_map(|(_, value)| longest_in(value))
+        .max_by_key(|value| value.len())
+}
+
+fn longest_in(value: &mut Value) -> Option<&mut String> {
+    match value {
+        Value::String(value) => Some(value),
+        Value::Array(values) => values
+            .iter_mut()
+            .filter_map(longest_in)
+            .max_by_key(|value| value.len()),
+        Value::Object(fields) => fields
+            .values_mut()
+            .filter_map(longest_in)
+            .max_by_key(|value| value.len()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn truncate_entries() {
+        let payload = json!({
+            "eventTime": "2024-03-01T12:00:00.250+00:00",
+            "severity": "INFO",
+            "message": "a".repeat(300),
+            "request": { "body": "é".repeat(200) },
+            "logging.googleapis.com/trace": "projects/my-project/traces/4f6e8c1d",
+        });
+
+        let small = json!({ "message": "Fits" });
+        assert_eq!(truncate(small.clone(), 100), small.to_string());
+        assert_eq!(truncate(payload.clone(), 0), payload.to_string());
+
+        let entry = truncate(payload.clone(), 600);
+        assert!(entry.len() <= 600);
+
+        let entry: Value = serde_json::from_str(&entry).unwrap();
+        assert_eq!(entry["truncated"], true);
+        assert_eq!(entry["severity"], "INFO");
+        assert_eq!(entry["eventTime"], payload["eventTime"]);
+        assert_eq!(
+            entry["logging.googleapis.com/trace"],
+            payload["logging.googleapis.com/trace"]
+        );
+
+        // The longest string is truncated first
+        assert_eq!(entry["message"], payload["message"]);
+        let body = entry["request"]["body"].as_str().unwrap();
+        assert!(body.starts_with('é') && body.len() < 400);
+    }
+
+    #[test]
+    fn oversized_fields() {
+        let payload = json!({
+            "eventTime": "2024-03-01T12:00:00.250+00:00",
+            "severity": "INFO",
+            "message": "Too many samples",
+            "samples": vec![1; 200],
+        });
+        let entry: Value = serde_json::from_str(&truncate(payload, 200)).unwrap();
+        assert_eq!(entry["truncated"], true);
+        assert!(entry.get("samples").is_none());
+
+        // Only reserved fields are large
+        let payload = json!({
+            "eventTime": "2024-03-01T12:00:00.250+00:00",
+            "severity": "INFO",
+            "message": "Large request",
+            "httpRequest": { "requestUrl": "a".repeat(300) },
+            "logging.googleapis.com/labels": { "request": "b".repeat(300) },
+        });
+        let original_len = payload.to_string().len();
+        let entry = truncate(payload, 200);
+        assert!(entry.len() <= 200);
+
+        let entry: Value = serde_json::from_str(&entry).unwrap();
+        assert_eq!(
+            entry,
+            json!({
+                "eventTime": "2024-03-01T12:00:00.250+00:00",
+                "severity": "INFO",
+                "message": format!(
+                    "Entry of {} bytes dropped, over the 200 bytes limit",
+                    original_len
+                ),
+                "truncated": true,
+            })
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    fmt,
+    io::Write,
+    sync::{Arc, RwLock},
+};
+
+use env_logger::filter::{Builder as FilterBuilder, Filter};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::{
+    audit, breaker, context,
+    directives::{self, FieldDirective},
+    floor, overhead, partitions, stats, trace,
+    writer::{SharedWriter, Worker},
+    Target,
+};
+
+// Entries emitted by the logger itself, they bypass filters
+const SELF_LOG_TARGET: &str = "stackdriver_logger";
+
+// Filters taking precedence over RUST_LOG, set at runtime
+static OVERRIDE: RwLock<Option<Override>> = RwLock::new(None);
+
+// RUST_LOG filters and their max level, restored when the override is removed
+static DEFAULT_FILTERS: RwLock<String> = RwLock::new(String::new());
+static DEFAULT_FIELDS: RwLock<Vec<FieldDirective>> = RwLock::new(Vec::new());
+static DEFAULT_LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::Off);
+
+// Keep the directives around so the current configuration can be reported
+struct Override {
+    directives: String,
+    filter: Filter,
+    fields: Vec<FieldDirective>,
+}
+
+// Loggers built by the initializers, they can be replaced at runtime
+static LOGGERS: RwLock<Option<Arc<Loggers>>> = RwLock::new(None);
+
+// The underlying env_logger loggers, so the effective filters can change at runtime.
+// Both loggers share the same output format, `verbose` accepts everything and is used
+// while an override is active, or for records only enabled by a field directive.
+pub(crate) struct Loggers {
+    pub(crate) default: Box<dyn Log>,
+    // RUST_LOG, field directives included, `default` must be built without them
+    pub(crate) default_filters: String,
+    pub(crate) default_level: LevelFilter,
+    pub(crate) verbose: Box<dyn Log>,
+    // Custom or non-blocking writers, env_logger doesn't flush them
+    pub(crate) writers: Vec<SharedWriter>,
+    // Threads of non-blocking writers
+    pub(crate) workers: Vec<Worker>,
+    // Where entries end up, checked by `selftest`
+    pub(crate) sinks: Vec<Sink>,
+    // Whether entries are formatted by pretty_env_logger
+    pub(crate) pretty: bool,
+}
+
+// A stream or writer receiving entries
+#[derive(Clone)]
+pub(crate) struct Sink {
+    pub(crate) name: &'static str,
+    // `None` if env_logger writes to the stream directly
+    pub(crate) writer: Option<SharedWriter>,
+}
+
+// Sinks of a target, given the writers used for each stream
+pub(crate) fn stream_sinks(
+    target: Target,
+    stdout: Option<SharedWriter>,
+    stderr: Option<SharedWriter>,
+) -> Vec<Sink> {
+    let stdout = Sink {
+        name: "stdout",
+        writer: stdout,
+    };
+    let stderr = Sink {
+        name: "stderr",
+        writer: stderr,
+    };
+    match target {
+        Target::Stdout => vec![stdout],
+        Target::Stderr => vec![stderr],
+        Target::Split => vec![stdout, stderr],
+    }
+}
+
+// Send warnings and errors to one logger, other entries to another one
+struct SplitLogger {
+    low: Box<dyn Log>,
+    high: Box<dyn Log>,
+}
+
+impl Log for SplitLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.level() <= Level::Warn {
+            self.high.enabled(metadata)
+        } else {
+            self.low.enabled(metadata)
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if record.level() <= Level::Warn {
+            self.high.log(record)
+        } else {
+            self.low.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.low.flush();
+        self.high.flush();
+    }
+}
+
+// Build a logger for the target, with one logger per stream when split by severity
+pub(crate) fn routed<L, F>(target: Target, build: F) -> Box<dyn Log>
+where
+    L: Log + 'static,
+    F: Fn(Target) -> L,
+{
+    match target {
+        Target::Split => Box::new(SplitLogger {
+            low: Box::new(build(Target::Stdout)),
+            high: Box::new(build(Target::Stderr)),
+        }),
+        target => Box::new(build(target)),
+    }
+}
+
+// Max level enabled by env_logger filters
+pub(crate) fn filters_level(filters: &str) -> LevelFilter {
+    FilterBuilder::new().parse(filters).build().filter()
+}
+
+// Global logger, forwards to the current loggers
+struct Logger;
+
+impl Logger {
+    // Whether the record passes regular filters, and whether the verbose logger should be used
+    fn filter(loggers: &Loggers, record: &Record<'_>) -> Option<bool> {
+        let overridden = OVERRIDE.read().ok();
+        let overridden = overridden.as_ref().and_then(|o| o.as_ref());
+
+        let (by_filters, fields_match) = match overridden {
+            Some(o) => (
+                o.filter.matches(record),
+                directives::matches(&o.fields, record),
+            ),
+            None => (
+                loggers.default.enabled(record.metadata()),
+                DEFAULT_FIELDS
+                    .read()
+                    .map(|fields| directives::matches(&fields, record))
+                    .unwrap_or(false),
+            ),
+        };
+
+        match (by_filters, fields_match) {
+            (true, _) => Some(overridden.is_some()),
+            (false, true) => Some(true),
+            (false, false) => None,
+        }
+    }
+}
+
+// Whether records may pass the filters or one of the field directives.
+// Field directives can't be checked without the record, only look at their level.
+fn enabled_by<F>(filters: F, fields: &[FieldDirective], metadata: &Metadata<'_>) -> bool
+where
+    F: Fn(&Metadata<'_>) -> bool,
+{
+    filters(metadata) || fields.iter().any(|field| metadata.level() <= field.level())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.target() == SELF_LOG_TARGET {
+            return true;
+        }
+
+        if metadata.level() > floor::max_level() {
+            return false;
+        }
+
+        let overridden = OVERRIDE.read().ok();
+        if let Some(o) = overridden.as_ref().and_then(|o| o.as_ref()) {
+            return enabled_by(|m| o.filter.enabled(m), &o.fields, metadata);
+        }
+
+        let loggers = match current_loggers() {
+            Some(loggers) => loggers,
+            None => return false,
+        };
+        match DEFAULT_FIELDS.read() {
+            Ok(fields) => enabled_by(|m| loggers.default.enabled(m), &fields, metadata),
+            Err(_) => loggers.default.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let _lazy_values = context::LazyCache::enter();
+
+        // Don't hold the lock while logging, formatting may log as well
+        let loggers = match current_loggers() {
+            Some(loggers) => loggers,
+            None => return,
+        };
+
+        if record.target() == SELF_LOG_TARGET {
+            return loggers.verbose.log(record);
+        }
+
+        if record.level() > floor::max_level() {
+            return;
+        }
+
+        let use_verbose = match Self::filter(&loggers, record) {
+            Some(use_verbose) => use_verbose,
+            None => return,
+        };
+
+        // Entries dropped by sampling don't count towards the budget
+        if !breaker::allow(record)
+            || !partitions::sampled(record)
+            || !trace::sampled(record)
+            || !context::within_budget(record)
+        {
+            stats::record_dropped();
+            return;
+        }
+
+        stats::record_emitted(record.level());
+        #[cfg(feature = "stream")]
+        crate::stream::publish(record);
+
+        overhead::measure(|| {
+            if use_verbose {
+                loggers.verbose.log(record);
+            } else {
+                loggers.default.log(record);
+            }
+        });
+    }
+
+    fn flush(&self) {
+        breaker::flush();
+        if let Some(loggers) = current_loggers() {
+            loggers.default.flush();
+            loggers.verbose.flush();
+            for mut writer in loggers.writers.iter().cloned() {
+                let _ = writer.flush();
+            }
+            partitions::flush();
+        }
+    }
+}
+
+// Install the global logger
+pub(crate) fn install(loggers: Loggers) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(Logger))?;
+    stats::mark_started();
+
+    set_loggers(loggers);
+    Ok(())
+}
+
+// Swap the loggers of an installed logger, returns false if it isn't installed
+pub(crate) fn replace(loggers: Loggers) -> bool {
+    if current_loggers().is_none() {
+        return false;
+    }
+
+    let before = audit::Snapshot::capture();
+    set_loggers(loggers);
+    audit::config_changed("upgrade", &before, &audit::Snapshot::capture());
+    true
+}
+
+// Whether entries are currently formatted by pretty_env_logger instead of JSON
+pub(crate) fn pretty() -> bool {
+    current_loggers().is_some_and(|loggers| loggers.pretty)
+}
+
+fn current_loggers() -> Option<Arc<Loggers>> {
+    LOGGERS.read().ok()?.clone()
+}
+
+fn set_loggers(loggers: Loggers) {
+    let (_, fields) = directives::split(&loggers.default_filters);
+    let default_level = fields
+        .iter()
+        .map(FieldDirective::level)
+        .fold(loggers.default_level, Ord::max);
+
+    if let Ok(mut filters) = DEFAULT_FILTERS.write() {
+        *filters = loggers.default_filters.clone();
+    }
+    if let Ok(mut current) = DEFAULT_FIELDS.write() {
+        *current = fields;
+    }
+    if let Ok(mut level) = DEFAULT_LEVEL.write() {
+        *level = default_level;
+    }
+    if let Ok(mut current) = LOGGERS.write() {
+        *current = Some(Arc::new(loggers));
+    }
+    refresh_max_level();
+}
+
+// Replace RUST_LOG filters with the given directives, or restore them with `None`.
+// `source` is reported along with the change, see `audit::config_changed`.
+pub(crate) fn set_override(filters: Option<&str>, source: &str) {
+    let before = audit::Snapshot::capture();
+    let filters = filters.map(|f| {
+        let (env_filters, fields) = directives::split(f);
+        Override {
+            directives: f.to_owned(),
+            filter: FilterBuilder::new().parse(&env_filters).build(),
+            fields,
+        }
+    });
+    if let Ok(mut current) = OVERRIDE.write() {
+        *current = filters;
+    }
+
+    refresh_max_level();
+    audit::config_changed(source, &before, &audit::Snapshot::capture());
+}
+
+// Directives currently in effect, either the override or RUST_LOG
+pub(crate) fn current_filters() -> String {
+    let overridden = OVERRIDE
+        .read()
+        .ok()
+        .and_then(|o| o.as_ref().map(|o| o.directives.clone()));
+
+    overridden
+        .or_else(|| DEFAULT_FILTERS.read().ok().map(|f| f.clone()))
+        .unwrap_or_default()
+}
+
+// RUST_LOG filters, restored when the override is removed
+#[cfg(all(unix, feature = "signals"))]
+pub(crate) fn default_filters() -> String {
+    DEFAULT_FILTERS
+        .read()
+        .map(|f| f.clone())
+        .unwrap_or_default()
+}
+
+// Sinks of the installed logger, if any
+pub(crate) fn sinks() -> Option<Vec<Sink>> {
+    Some(current_loggers()?.sinks.clone())
+}
+
+// Write an already formatted entry, returns false if no logger is installed
+pub(crate) fn emit_raw(level: Level, entry: &str) -> bool {
+    let loggers = match current_loggers() {
+        Some(loggers) => loggers,
+        None => return false,
+    };
+
+    loggers.verbose.log(
+        &Record::builder()
+            .args(format_args!("{}", entry))
+            .level(level)
+            .target(SELF_LOG_TARGET)
+            .build(),
+    );
+    true
+}
+
+// Emit an entry about the logger itself, regardless of the current filters
+pub(crate) fn self_log(level: Level, args: fmt::Arguments<'_>) {
+    log::logger().log(
+        &Record::builder()
+            .args(args)
+            .level(level)
+            .target(SELF_LOG_TARGET)
+            .module_path_static(Some(module_path!()))
+            .file_static(Some(file!()))
+            .line(Some(line!()))
+            .build(),
+    );
+}
+
+// Update the max level of the log crate, so disabled records are skipped early
+pub(crate) fn refresh_max_level() {
+    let default = DEFAULT_LEVEL.read().map(|l| *l).unwrap_or(LevelFilter::Off);
+    let level = OVERRIDE
+        .read()
+        .ok()
+        .as_ref()
+        .and_then(|o| {
+            o.as_ref().map(|o| {
+                o.fields
+                    .iter()
+                    .map(FieldDirective::level)
+                    .fold(o.filter.filter(), Ord::max)
+            })
+        })
+        .unwrap_or(default);
+
+    log::set_max_level(level.min(floor::max_level()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enabled_per_target() {
+        let (filters, _) = directives::split("info,my_app=debug");
+        let default = FilterBuilder::new().parse(&filters).build();
+        let (_, fields) = directives::split("info,tenant=acme=trace");
+        let metadata = |target, level| Metadata::builder().target(target).level(level).build();
+
+        let enabled = |metadata: &Metadata<'_>| enabled_by(|m| default.enabled(m), &[], metadata);
+        assert!(enabled(&metadata("my_app", Level::Debug)));
+        assert!(!enabled(&metadata("other", Level::Debug)));
+        assert!(enabled(&metadata("other", Level::Info)));
+
+        let with_fields =
+            |metadata: &Metadata<'_>| enabled_by(|m| default.enabled(m), &fields, metadata);
+        assert!(with_fields(&metadata("other", Level::Trace)));
+    }
+}
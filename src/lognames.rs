@@ -0,0 +1,67 @@
+use std::sync::RwLock;
+
+use serde_json::{json, Value};
+
+use crate::formatters::matches_target;
+
+// Log names registered per target
+static LOG_NAMES: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+
+// Special field selecting the log an entry is written to
+const LOG_NAME_KEY: &str = "logging.googleapis.com/logName";
+
+/// Write the records of a target to a separate log, e.g. to keep audit events apart from
+/// application logs.
+///
+/// The log id is emitted as `logging.googleapis.com/logName`. It applies to the target and
+/// its submodules, like `RUST_LOG` directives, the most specific target wins. Other targets
+/// are written to the default log. Registering a target again replaces its log id.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_log_name("audit", "audit");
+/// stackdriver_logger::set_log_name("my_app::http::access", "access");
+/// ```
+pub fn set_log_name(target: &str, log_id: &str) {
+    if let Ok(mut log_names) = LOG_NAMES.write() {
+        log_names.retain(|(t, _)| t != target);
+        log_names.push((target.to_owned(), log_id.to_owned()));
+    }
+}
+
+// Add the log name of the record target, if any
+pub(crate) fn insert_log_name(payload: &mut Value, record: &log::Record<'_>) {
+    let log_names = match LOG_NAMES.read() {
+        Ok(log_names) => log_names,
+        Err(_) => return,
+    };
+
+    let log_name = log_names
+        .iter()
+        .filter(|(target, _)| matches_target(target, record.target()))
+        .max_by_key(|(target, _)| target.len());
+
+    if let Some((_, log_id)) = log_name {
+        payload[LOG_NAME_KEY] = json!(log_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_target() {
+        set_log_name("log_names", "app");
+        set_log_name("log_names::access", "access");
+
+        let log_name = |target| {
+            let mut payload = json!({});
+            insert_log_name(&mut payload, &log::Record::builder().target(target).build());
+            payload.get(LOG_NAME_KEY).cloned()
+        };
+        assert_eq!(log_name("log_names::access::v2"), Some(json!("access")));
+        assert_eq!(log_name("log_names::db"), Some(json!("app")));
+        assert_eq!(log_name("other"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{try_init, Service};
+use crate::{try_init_with, InitError, Service};
 use toml::Value;
 
 /// Initialize the logger using your project's TOML file.
@@ -34,6 +34,43 @@ macro_rules! init_with_cargo {
     }};
 }
 
+/// Fallible version of `init_with_cargo!`, returns `InitError::Config` if the package name or
+/// version can't be read.
+/// ## Usage
+/// ```rust
+/// match stackdriver_logger::try_init_with_cargo!() {
+///     Ok(()) => log::info!("Logger initialized"),
+///     Err(e) => eprintln!("Logging is not available: {}", e),
+/// }
+/// ```
+#[macro_export]
+macro_rules! try_init_with_cargo {
+    ($e:expr) => {{
+        let base = include_str!($e);
+        $crate::macros::try_read_cargo(base)
+    }};
+    () => {{
+        let base = include_str!("../Cargo.toml");
+        $crate::macros::try_read_cargo(base)
+    }};
+}
+
+#[doc(hidden)]
+pub fn try_read_cargo(input: &str) -> Result<(), InitError> {
+    let toml = input
+        .parse::<Value>()
+        .map_err(|e| InitError::Config(e.to_string()))?;
+
+    let service = Service {
+        name: read_package_key(&toml, "name")
+            .ok_or_else(|| InitError::Config("missing package name".to_owned()))?,
+        version: read_package_key(&toml, "version")
+            .ok_or_else(|| InitError::Config("missing package version".to_owned()))?,
+    };
+
+    try_init_with(Some(service), true)
+}
+
 #[doc(hidden)]
 pub fn read_cargo(input: &str) {
     input
@@ -45,7 +82,7 @@ pub fn read_cargo(input: &str) {
                 version: read_package_key(&toml, "version")?,
             };
 
-            try_init(Some(service), true).expect("Could not initialize stackdriver_logger");
+            try_init_with(Some(service), true).expect("Could not initialize stackdriver_logger");
             None
         });
 }
@@ -0,0 +1,124 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use serde_json::{json, Value};
+
+use crate::{context, Context, ContextGuard};
+
+// Special field grouping the entries of an operation
+const OPERATION_KEY: &str = "logging.googleapis.com/operation";
+
+/// A long-running operation logging several entries, grouped in the Logs Explorer through
+/// `logging.googleapis.com/operation`.
+///
+/// Entries emitted while the operation is entered carry its id and producer. The first one
+/// is marked with `first`, entries logged within [`Operation::last`] are marked with `last`.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::Operation;
+///
+/// stackdriver_logger::init();
+///
+/// let operation = Operation::new("import-42", "my_app/import");
+/// let _guard = operation.enter();
+/// info!("Import started");
+/// info!("Importing");
+/// operation.last().scope(|| info!("Import done"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Operation {
+    id: String,
+    producer: String,
+    // Shared by every clone, only one entry is the first
+    started: Arc<AtomicBool>,
+    last: bool,
+}
+
+impl Operation {
+    /// Create an operation, the id only needs to be unique for its producer.
+    pub fn new<I: Into<String>, P: Into<String>>(id: I, producer: P) -> Self {
+        Self {
+            id: id.into(),
+            producer: producer.into(),
+            started: Arc::new(AtomicBool::new(false)),
+            last: false,
+        }
+    }
+
+    /// The same operation, marking its entries as the last ones.
+    pub fn last(&self) -> Self {
+        Self {
+            last: true,
+            ..self.clone()
+        }
+    }
+
+    /// Enter this operation, within the current context, until the guard is dropped.
+    pub fn enter(&self) -> ContextGuard {
+        self.context().enter()
+    }
+
+    /// Run a closure with this operation entered, within the current context.
+    pub fn scope<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.context().scope(f)
+    }
+
+    fn context(&self) -> Context {
+        Context::current().with_operation(self.clone())
+    }
+}
+
+// Add the operation of the current context, if any
+pub(crate) fn insert_operation(payload: &mut Value) {
+    let operation = match context::current_operation() {
+        Some(operation) => operation,
+        None => return,
+    };
+
+    let mut value = json!({
+        "id": operation.id,
+        "producer": operation.producer,
+    });
+    if !operation.started.swap(true, Ordering::Relaxed) {
+        value["first"] = json!(true);
+    }
+    if operation.last {
+        value["last"] = json!(true);
+    }
+    payload[OPERATION_KEY] = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_and_last() {
+        let operation = Operation::new("import-42", "my_app/import");
+        let insert = || {
+            let mut payload = json!({});
+            insert_operation(&mut payload);
+            payload
+        };
+
+        assert_eq!(insert(), json!({}));
+        let _guard = operation.enter();
+        assert_eq!(
+            insert()[OPERATION_KEY],
+            json!({ "id": "import-42", "producer": "my_app/import", "first": true })
+        );
+        assert_eq!(
+            insert()[OPERATION_KEY],
+            json!({ "id": "import-42", "producer": "my_app/import" })
+        );
+        operation.last().scope(|| {
+            assert_eq!(
+                insert()[OPERATION_KEY],
+                json!({ "id": "import-42", "producer": "my_app/import", "last": true })
+            );
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+// Measure time spent formatting and writing each entry
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Power of two buckets, bucket `i` counts durations below `2^(i + 1)` nanoseconds
+const BUCKETS: usize = 40;
+static HISTOGRAM: [AtomicUsize; BUCKETS] = [const { AtomicUsize::new(0) }; BUCKETS];
+static MAX_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// Time spent formatting and writing entries, see `set_overhead_tracking`.
+///
+/// Percentiles are upper bounds, durations are grouped in power of two buckets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overhead {
+    /// Number of entries measured
+    pub samples: usize,
+    /// Median duration
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Exact maximum duration
+    pub max: Duration,
+}
+
+/// Measure the time spent formatting and writing each entry.
+///
+/// This quantifies the logging overhead in production, read it with `logging_overhead`.
+/// Measuring takes two clock reads per entry, it is disabled by default.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_overhead_tracking(true);
+/// // ...
+/// let overhead = stackdriver_logger::logging_overhead();
+/// println!("p99 logging overhead: {:?}", overhead.p99);
+/// ```
+pub fn set_overhead_tracking(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Percentiles of the time spent formatting and writing entries since tracking was enabled.
+pub fn logging_overhead() -> Overhead {
+    let counts: Vec<usize> = HISTOGRAM
+        .iter()
+        .map(|b| b.load(Ordering::Relaxed))
+        .collect();
+    let samples: usize = counts.iter().sum();
+
+    let percentile = |p: usize| {
+        let rank = (samples * p).div_ceil(100);
+        let mut seen = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank && seen > 0 {
+                return Duration::from_nanos(1 << (bucket + 1));
+            }
+        }
+        Duration::ZERO
+    };
+
+    Overhead {
+        samples,
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max: Duration::from_nanos(MAX_NANOS.load(Ordering::Relaxed)),
+    }
+}
+
+// Run the given closure, measuring it when tracking is enabled
+pub(crate) fn measure<F: FnOnce()>(f: F) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+
+    let start = Instant::now();
+    f();
+    record(start.elapsed());
+}
+
+fn record(elapsed: Duration) {
+    let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+    let bucket = (u64::BITS - nanos.leading_zeros()).saturating_sub(1) as usize;
+
+    HISTOGRAM[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    MAX_NANOS.fetch_max(nanos, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overhead_percentiles() {
+        for _ in 0..98 {
+            record(Duration::from_nanos(1000));
+        }
+        record(Duration::from_micros(50));
+        record(Duration::from_millis(3));
+
+        let overhead = logging_overhead();
+        assert_eq!(overhead.samples, 100);
+        assert_eq!(overhead.p50, Duration::from_nanos(1024));
+        assert_eq!(overhead.p90, Duration::from_nanos(1024));
+        assert_eq!(overhead.p99, Duration::from_nanos(65536));
+        assert_eq!(overhead.max, Duration::from_millis(3));
+    }
+}
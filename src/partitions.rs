@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use log::Level;
+
+use crate::{audit, context, writer::SharedWriter};
+
+// Context or custom field selecting the partition of a record
+static PARTITION_FIELD: RwLock<String> = RwLock::new(String::new());
+
+// Registered partitions, indexed by field value
+static PARTITIONS: RwLock<Option<HashMap<String, Arc<Partition>>>> = RwLock::new(None);
+
+// Field used if `set_partition_field` wasn't called
+const DEFAULT_PARTITION_FIELD: &str = "tenant";
+
+/// Settings applied to the records of a tenant, see [`register_partition`].
+#[derive(Clone, Debug, Default)]
+pub struct Partition {
+    labels: BTreeMap<String, String>,
+    sample_rate: Option<f64>,
+    writer: Option<SharedWriter>,
+    seen: Arc<AtomicU64>,
+}
+
+impl Partition {
+    /// Create a partition keeping every entry, without labels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a label to every entry of this partition.
+    pub fn with_label<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Only keep this share of `INFO`, `DEBUG` and `TRACE` entries, between 0 and 1.
+    /// Warnings and errors are always kept.
+    pub fn with_sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = Some(rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Write the entries of this partition to the given writer instead of the target.
+    pub fn with_writer<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.writer = Some(SharedWriter::new(writer));
+        self
+    }
+
+    // Sampling is deterministic, every entry adds `rate` and one is kept each time the sum
+    // crosses an integer
+    fn sampled(&self, level: Level) -> bool {
+        let rate = match self.sample_rate {
+            Some(rate) if level > Level::Warn => rate,
+            _ => return true,
+        };
+
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * rate).floor() > (seen * rate).floor()
+    }
+}
+
+/// Select partitions with this context or custom field, `tenant` by default.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_partition_field("customer_id");
+/// ```
+pub fn set_partition_field(name: &str) {
+    if let Ok(mut field) = PARTITION_FIELD.write() {
+        *field = name.to_owned();
+    }
+}
+
+/// Apply labels, a sample rate or a dedicated writer to the records of a tenant.
+///
+/// Records are matched on the partition field, see [`set_partition_field`]. Each partition
+/// is sampled on its own, so a noisy tenant can be throttled without affecting others.
+/// Registering a partition for the same value replaces it. Sample rate changes are reported
+/// by a `NOTICE` entry.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::{Context, Partition};
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::register_partition(
+///     "acme",
+///     Partition::new().with_label("tier", "free").with_sample_rate(0.1),
+/// );
+///
+/// Context::current().with_field("tenant", "acme").scope(|| {
+///     info!("Only one in ten of these entries is emitted");
+/// });
+/// ```
+pub fn register_partition(value: &str, partition: Partition) {
+    let before = audit::Snapshot::capture();
+    if let Ok(mut partitions) = PARTITIONS.write() {
+        partitions
+            .get_or_insert_with(HashMap::new)
+            .insert(value.to_owned(), Arc::new(partition));
+    }
+    audit::config_changed("register_partition", &before, &audit::Snapshot::capture());
+}
+
+/// Remove the partition registered for a tenant, if any.
+pub fn remove_partition(value: &str) {
+    let before = audit::Snapshot::capture();
+    if let Ok(mut partitions) = PARTITIONS.write() {
+        if let Some(partitions) = partitions.as_mut() {
+            partitions.remove(value);
+        }
+    }
+    audit::config_changed("remove_partition", &before, &audit::Snapshot::capture());
+}
+
+// Partition of a record, if any
+fn partition(record: &log::Record<'_>) -> Option<Arc<Partition>> {
+    let partitions = PARTITIONS.read().ok()?;
+    let partitions = partitions.as_ref().filter(|p| !p.is_empty())?;
+
+    let field = PARTITION_FIELD.read().ok()?;
+    let field = match field.as_str() {
+        "" => DEFAULT_PARTITION_FIELD,
+        field => field,
+    };
+
+    let value = context::field_value(record, field)?;
+    partitions.get(&value).cloned()
+}
+
+// Whether the record is kept by its partition sampling
+pub(crate) fn sampled(record: &log::Record<'_>) -> bool {
+    partition(record).is_none_or(|p| p.sampled(record.level()))
+}
+
+// Labels of the record partition
+pub(crate) fn labels(record: &log::Record<'_>) -> BTreeMap<String, String> {
+    partition(record)
+        .map(|p| p.labels.clone())
+        .unwrap_or_default()
+}
+
+// Writer of the record partition, if it has one
+pub(crate) fn writer(record: &log::Record<'_>) -> Option<SharedWriter> {
+    partition(record)?.writer.clone()
+}
+
+// Sample rates of the partitions, e.g. `acme=0.1,globex=0.5`, sorted by value
+pub(crate) fn sample_rates() -> String {
+    let partitions = match PARTITIONS.read() {
+        Ok(partitions) => partitions,
+        Err(_) => return String::new(),
+    };
+
+    let rates: BTreeMap<&str, f64> = partitions
+        .iter()
+        .flatten()
+        .filter_map(|(value, p)| Some((value.as_str(), p.sample_rate?)))
+        .collect();
+    rates
+        .iter()
+        .map(|(value, rate)| format!("{}={}", value, rate))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Flush the writers of every partition
+pub(crate) fn flush() {
+    let writers: Vec<SharedWriter> = match PARTITIONS.read() {
+        Ok(partitions) => partitions
+            .iter()
+            .flat_map(|p| p.values())
+            .filter_map(|p| p.writer.clone())
+            .collect(),
+        Err(_) => return,
+    };
+
+    for mut writer in writers {
+        let _ = writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rate() {
+        let partition = Partition::new().with_sample_rate(0.25);
+        let kept = (0..100).filter(|_| partition.sampled(Level::Info)).count();
+        assert_eq!(kept, 25);
+
+        assert!((0..10).all(|_| partition.sampled(Level::Warn)));
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{Map, Value};
+
+// Merge JSON object messages into the payload
+static PASSTHROUGH: AtomicBool = AtomicBool::new(false);
+
+// Fields set by the formatter that embedded objects can't overwrite, along with the
+// timestamp fields recognized by the logging agent. The configured timestamp key is
+// reserved as well, see `reserved`.
+const RESERVED_KEYS: [&str; 8] = [
+    "eventTime",
+    "time",
+    "timestamp",
+    "timestampSeconds",
+    "timestampNanos",
+    "severity",
+    "serviceContext",
+    "reportLocation",
+];
+
+// Special fields like the trace or labels, embedded objects can't set them either
+const RESERVED_PREFIX: &str = "logging.googleapis.com/";
+
+/// Merge messages that are JSON objects into the payload instead of encoding them as a string.
+///
+/// This is meant for embedded libraries already emitting structured logs. The object's
+/// `message` key becomes the entry message, other keys are added to the payload, except for
+/// the ones set by the formatter like `severity`, timestamp keys and `logging.googleapis.com/*`
+/// special fields. Other messages are left untouched.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_json_passthrough(true);
+/// log::info!(r#"{{"message": "Cache warmed", "entries": 1200}}"#);
+/// ```
+pub fn set_json_passthrough(enabled: bool) {
+    PASSTHROUGH.store(enabled, Ordering::Relaxed);
+}
+
+// Merge the record message into the payload if it's a JSON object and passthrough is enabled
+pub(crate) fn merge_message(payload: &mut Value, record: &log::Record<'_>, timestamp_key: &str) {
+    if !PASSTHROUGH.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Some(object) = parse_object(&record.args().to_string()) {
+        merge_object(payload, object, timestamp_key);
+    }
+}
+
+fn parse_object(message: &str) -> Option<Map<String, Value>> {
+    // Avoid parsing the vast majority of messages
+    if !message.trim_start().starts_with('{') {
+        return None;
+    }
+
+    match serde_json::from_str(message) {
+        Ok(Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+fn merge_object(payload: &mut Value, object: Map<String, Value>, timestamp_key: &str) {
+    let payload = match payload.as_object_mut() {
+        Some(payload) => payload,
+        None => return,
+    };
+
+    if !object.contains_key("message") {
+        payload.remove("message");
+    }
+
+    for (key, value) in object {
+        if !reserved(&key, timestamp_key) {
+            payload.insert(key, value);
+        }
+    }
+}
+
+fn reserved(key: &str, timestamp_key: &str) -> bool {
+    key == timestamp_key || RESERVED_KEYS.contains(&key) || key.starts_with(RESERVED_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_json_messages() {
+        let message = r#"{"message": "Cache warmed", "entries": 1200, "severity": "DEBUG"}"#;
+        let mut payload = json!({ "severity": "INFO", "message": message });
+        merge_object(&mut payload, parse_object(message).unwrap(), "eventTime");
+
+        let expected = json!({ "severity": "INFO", "message": "Cache warmed", "entries": 1200 });
+        assert_eq!(payload, expected);
+
+        let message = r#"{"event": "started"}"#;
+        let mut payload = json!({ "severity": "INFO", "message": message });
+        merge_object(&mut payload, parse_object(message).unwrap(), "eventTime");
+        assert_eq!(payload, json!({ "severity": "INFO", "event": "started" }));
+
+        assert!(parse_object("[1, 2]").is_none());
+        assert!(parse_object("{not json").is_none());
+        assert!(parse_object("plain message").is_none());
+    }
+
+    #[test]
+    fn reserved_keys() {
+        let message = r#"{"message": "Forged", "ts": 1, "timestampSeconds": 1, "logging.googleapis.com/trace": "forged"}"#;
+        let mut payload = json!({ "message": message });
+        merge_object(&mut payload, parse_object(message).unwrap(), "ts");
+        assert_eq!(payload, json!({ "message": "Forged" }));
+    }
+}
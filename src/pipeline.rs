@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::{
+    bigquery, current_service, format_record, formatters, honeycomb,
+    timestamp::{self, TimestampFormat, TimestampPrecision},
+    Format, Service,
+};
+
+// Extra step run on the payload of a record
+type Stage = Arc<dyn Fn(&log::Record<'_>, Value) -> Value + Send + Sync>;
+
+/// The steps turning a record into an entry, usable without installing a logger.
+///
+/// A pipeline builds the same entries as the installed logger: default payload, formatters
+/// registered with `set_target_formatter`, then its own stages, then the output format.
+/// Drive it with synthetic records to unit test redaction or enrichment stages.
+/// ## Usage
+/// ```rust
+/// use serde_json::json;
+/// use stackdriver_logger::{Pipeline, Service};
+///
+/// let pipeline = Pipeline::new()
+///     .service(Service {
+///         name: "my_app".to_owned(),
+///         version: "1.0.0".to_owned(),
+///     })
+///     .stage(|_record, mut payload| {
+///         payload["message"] = json!("[redacted]");
+///         payload
+///     });
+///
+/// let record = log::Record::builder()
+///     .args(format_args!("Card 4242-4242-4242-4242 declined"))
+///     .level(log::Level::Warn)
+///     .build();
+/// let entry = pipeline.process(&record);
+/// assert_eq!(entry["message"], "[redacted]");
+/// assert_eq!(entry["severity"], "WARNING");
+/// ```
+#[derive(Clone)]
+pub struct Pipeline {
+    service: Option<Service>,
+    report_location: bool,
+    format: Format,
+    timestamp_key: String,
+    timestamp_format: TimestampFormat,
+    timestamp_precision: TimestampPrecision,
+    stages: Vec<Stage>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            service: None,
+            report_location: true,
+            format: Format::default(),
+            timestamp_key: timestamp::DEFAULT_KEY.to_owned(),
+            timestamp_format: TimestampFormat::default(),
+            timestamp_precision: TimestampPrecision::default(),
+            stages: Vec::new(),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Create a pipeline with the default options of the installed logger.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Service reported in entries, the logger's service by default.
+    pub fn service(mut self, service: Service) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    /// Whether entries include the record location, true by default.
+    pub fn report_location(mut self, report_location: bool) -> Self {
+        self.report_location = report_location;
+        self
+    }
+
+    /// How entries are encoded, Stackdriver JSON by default.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Key of the entry timestamp, `eventTime` by default. Only affects the Stackdriver format.
+    pub fn timestamp_key(mut self, key: &str) -> Self {
+        self.timestamp_key = key.to_owned();
+        self
+    }
+
+    /// How the entry timestamp is represented, RFC3339 by default.
+    /// Only affects the Stackdriver format.
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Fractional digits of the entry timestamp, as many as needed by default.
+    /// Only affects the Stackdriver format.
+    pub fn timestamp_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = precision;
+        self
+    }
+
+    /// Add a stage, run after the default payload is built and before it is encoded.
+    /// Stages run in the order they are added.
+    pub fn stage<F>(mut self, stage: F) -> Self
+    where
+        F: Fn(&log::Record<'_>, Value) -> Value + Send + Sync + 'static,
+    {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    /// Build the entry of a record, as it would be emitted.
+    pub fn process(&self, record: &log::Record<'_>) -> Value {
+        let service = self.service.clone().or_else(current_service);
+        let time = timestamp::record_time();
+        let payload = format_record(
+            record,
+            time,
+            service.as_ref(),
+            self.report_location,
+            &self.timestamp_key,
+        );
+        let payload = formatters::apply(record, payload);
+        let payload = self
+            .stages
+            .iter()
+            .fold(payload, |payload, stage| stage(record, payload));
+
+        match self.format {
+            Format::Stackdriver => {
+                let mut payload = payload;
+                timestamp::reshape(
+                    &mut payload,
+                    time,
+                    &self.timestamp_key,
+                    self.timestamp_format,
+                    self.timestamp_precision,
+                );
+                payload
+            }
+            Format::Honeycomb => honeycomb::encode(payload),
+            Format::BigQuery => bigquery::encode(payload),
+        }
+    }
+}
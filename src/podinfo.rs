@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::RwLock, thread, time::Duration};
+
+// Labels picked from the downward API file, shared with the formatter
+static POD_LABELS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+// Kubernetes refreshes projected volumes periodically, polling is good enough here
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Read a Kubernetes downward API labels (or annotations) file and merge selected entries into
+/// the `logging.googleapis.com/labels` field of every log entry.
+///
+/// Only keys starting with one of the given prefixes are kept. The file is read once right away,
+/// then re-read in a background thread so label updates on a running pod are picked up.
+/// ## Usage
+/// Assuming your pod spec mounts its labels under `/etc/podinfo` :
+/// ```rust,no_run
+/// stackdriver_logger::init();
+/// stackdriver_logger::watch_pod_labels("/etc/podinfo/labels", &["app.kubernetes.io/"]);
+/// ```
+pub fn watch_pod_labels<P: Into<PathBuf>>(path: P, prefixes: &[&str]) {
+    let path = path.into();
+    let prefixes: Vec<String> = prefixes.iter().map(|p| (*p).to_owned()).collect();
+
+    let mut last = fs::read_to_string(&path).ok();
+    store_labels(last.as_deref(), &prefixes);
+
+    thread::Builder::new()
+        .name("stackdriver-podinfo".to_owned())
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = fs::read_to_string(&path).ok();
+            if current != last {
+                store_labels(current.as_deref(), &prefixes);
+                last = current;
+            }
+        })
+        .expect("Could not spawn stackdriver_logger podinfo watcher");
+}
+
+// Labels currently picked from the downward API, if any
+pub(crate) fn pod_labels() -> BTreeMap<String, String> {
+    POD_LABELS
+        .read()
+        .map(|labels| labels.clone())
+        .unwrap_or_default()
+}
+
+fn store_labels(content: Option<&str>, prefixes: &[String]) {
+    let labels = content
+        .map(|c| parse_labels(c, prefixes))
+        .unwrap_or_default();
+
+    if let Ok(mut current) = POD_LABELS.write() {
+        *current = labels;
+    }
+}
+
+// The downward API writes one `key="value"` pair per line, values are quoted Go-style
+fn parse_labels(content: &str, prefixes: &[String]) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if !prefixes.iter().any(|p| key.starts_with(p.as_str())) {
+                return None;
+            }
+
+            Some((key.to_owned(), unquote(value.trim())))
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    let inner = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return value.to_owned(),
+    };
+
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_downward_api_labels() {
+        let content = "app.kubernetes.io/name=\"my-app\"\n\
+                       app.kubernetes.io/version=\"1.2.3\"\n\
+                       pod-template-hash=\"5d9f8c\"\n\
+                       app.kubernetes.io/part-of=\"a \\\"quoted\\\" value\"";
+
+        let labels = parse_labels(content, &["app.kubernetes.io/".to_owned()]);
+
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels["app.kubernetes.io/name"], "my-app");
+        assert_eq!(labels["app.kubernetes.io/version"], "1.2.3");
+        assert_eq!(labels["app.kubernetes.io/part-of"], "a \"quoted\" value");
+    }
+}
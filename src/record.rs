@@ -0,0 +1,195 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
+use log::Level;
+use serde_json::{json, Value};
+
+use crate::{clock, timestamp, Context};
+
+/// Owned snapshot of a record, cheap to keep around and serializable.
+///
+/// Fields include the current context and, with the `customfields` feature, the record
+/// key-values, all rendered as strings. Snapshots can be persisted as JSON and emitted
+/// again later, e.g. for forensics. They are formatted again when emitted : `replay` and the
+/// crash buffer work on already formatted entries instead, and don't use this type.
+/// ## Usage
+/// ```rust
+/// use stackdriver_logger::OwnedRecord;
+///
+/// stackdriver_logger::init();
+///
+/// let record = log::Record::builder()
+///     .args(format_args!("Payment failed"))
+///     .level(log::Level::Error)
+///     .target("my_app")
+///     .build();
+/// let snapshot = OwnedRecord::from_record(&record);
+///
+/// let json = snapshot.to_json().to_string();
+/// let restored = OwnedRecord::from_json(&serde_json::from_str(&json).unwrap()).unwrap();
+/// restored.emit();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedRecord {
+    /// When the snapshot was taken
+    pub timestamp: SystemTime,
+    /// Level of the record
+    pub level: Level,
+    /// Target of the record, the module path unless set explicitly
+    pub target: String,
+    /// Formatted message
+    pub message: String,
+    /// Module the record was logged from, if known
+    pub module_path: Option<String>,
+    /// Source file the record was logged from, if known
+    pub file: Option<String>,
+    /// Line the record was logged from, if known
+    pub line: Option<u32>,
+    /// Context fields and record key-values, the latter taking precedence
+    pub fields: BTreeMap<String, String>,
+}
+
+impl OwnedRecord {
+    /// Take a snapshot of a record, along with the current context.
+    pub fn from_record(record: &log::Record<'_>) -> Self {
+        #[cfg_attr(not(feature = "customfields"), allow(unused_mut))]
+        let mut fields = Context::current().fields();
+
+        #[cfg(feature = "customfields")]
+        {
+            let mut custom_fields = crate::fields::CustomFields::new();
+            if record.key_values().visit(&mut custom_fields).is_ok() {
+                for (key, value) in custom_fields.inner() {
+                    fields.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        Self {
+            timestamp: SystemTime::now(),
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(str::to_owned),
+            file: record.file().map(str::to_owned),
+            line: record.line(),
+            fields,
+        }
+    }
+
+    /// Serialize the snapshot, see `from_json` to restore it.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": clock::to_rfc3339(self.timestamp),
+            "level": self.level.as_str(),
+            "target": self.target,
+            "message": self.message,
+            "modulePath": self.module_path,
+            "file": self.file,
+            "line": self.line,
+            "fields": self.fields,
+        })
+    }
+
+    /// Restore a snapshot serialized with `to_json`, `None` if the value doesn't match.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key: &str| value.get(key)?.as_str().map(str::to_owned);
+
+        let fields = match value.get("fields") {
+            Some(Value::Object(fields)) => fields
+                .iter()
+                .map(|(k, v)| Some((k.clone(), v.as_str()?.to_owned())))
+                .collect::<Option<_>>()?,
+            Some(Value::Null) | None => BTreeMap::new(),
+            Some(_) => return None,
+        };
+
+        Some(Self {
+            timestamp: clock::parse_rfc3339(value.get("timestamp")?.as_str()?)?,
+            level: value.get("level")?.as_str()?.parse().ok()?,
+            target: string("target")?,
+            message: string("message")?,
+            module_path: string("modulePath"),
+            file: string("file"),
+            line: value
+                .get("line")
+                .and_then(Value::as_u64)
+                .and_then(|l| u32::try_from(l).ok()),
+            fields,
+        })
+    }
+
+    /// Emit the snapshot again through the installed logger, fields are added to the context.
+    /// The entry keeps the timestamp of the snapshot.
+    pub fn emit(&self) {
+        let context = self
+            .fields
+            .iter()
+            .fold(Context::current(), |context, (key, value)| {
+                context.with_field(key.as_str(), value)
+            });
+
+        context.scope(|| {
+            timestamp::emitted_at(self.timestamp, || {
+                log::logger().log(
+                    &log::Record::builder()
+                        .args(format_args!("{}", self.message))
+                        .level(self.level)
+                        .target(&self.target)
+                        .module_path(self.module_path.as_deref())
+                        .file(self.file.as_deref())
+                        .line(self.line)
+                        .build(),
+                )
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let snapshot = Context::new().with_field("tenant", "acme").scope(|| {
+            OwnedRecord::from_record(
+                &log::Record::builder()
+                    .args(format_args!("Payment failed"))
+                    .level(Level::Error)
+                    .target("my_app")
+                    .file(Some("src/main.rs"))
+                    .line(Some(12))
+                    .build(),
+            )
+        });
+        assert_eq!(
+            snapshot.fields.get("tenant").map(String::as_str),
+            Some("acme")
+        );
+
+        let restored = OwnedRecord::from_json(&snapshot.to_json());
+        assert_eq!(restored.as_ref(), Some(&snapshot));
+
+        assert_eq!(OwnedRecord::from_json(&json!({ "message": "Info!" })), None);
+    }
+
+    #[test]
+    fn emitted_with_snapshot_time() {
+        let output = crate::testing::install_logger();
+        let snapshot = OwnedRecord {
+            timestamp: clock::parse_rfc3339("2024-03-01T12:00:00.250+00:00").unwrap(),
+            level: Level::Warn,
+            target: "my_app".to_owned(),
+            message: "Deferred warning".to_owned(),
+            module_path: None,
+            file: None,
+            line: None,
+            fields: BTreeMap::new(),
+        };
+        snapshot.emit();
+
+        let entries = crate::testing::entries_with_message(&output, "Deferred warning");
+        let emitted = clock::parse_rfc3339(entries[0]["eventTime"].as_str().unwrap());
+        assert_eq!(emitted, Some(snapshot.timestamp));
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufRead},
+};
+
+use log::Level;
+use serde_json::Value;
+
+use crate::logger;
+
+thread_local! {
+    // Entry being replayed, written as is instead of formatting the record
+    static RAW_ENTRY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Write previously emitted JSON entries again, through the installed logger output.
+///
+/// Each line is written unchanged, so timestamps, `insertId` and every other field are
+/// preserved. This allows backfilling entries saved to local files while Cloud Logging was
+/// unreachable. Filters don't apply, the severity of each entry only selects the stream
+/// with `Target::Split`. Empty lines and lines which aren't JSON objects are skipped.
+/// Returns the number of replayed entries, fails if no logger is installed.
+/// ## Usage
+/// ```rust
+/// use std::{fs::File, io::BufReader};
+///
+/// stackdriver_logger::init();
+///
+/// # let path = std::env::temp_dir().join("saved.log");
+/// # std::fs::write(&path, "{\"message\":\"Saved\",\"severity\":\"INFO\"}\n").unwrap();
+/// let saved = BufReader::new(File::open(path).unwrap());
+/// let replayed = stackdriver_logger::replay(saved).unwrap();
+/// ```
+pub fn replay<R: BufRead>(input: R) -> io::Result<usize> {
+    let mut replayed = 0;
+    for line in input.lines() {
+        let line = line?;
+        let severity = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Object(entry)) => entry
+                .get("severity")
+                .and_then(Value::as_str)
+                .map(level)
+                .unwrap_or(Level::Info),
+            _ => continue,
+        };
+
+        let line = line.trim().to_owned();
+        let emitted = RAW_ENTRY.with(|raw| {
+            *raw.borrow_mut() = Some(line.clone());
+            let emitted = logger::emit_raw(severity, &line);
+            raw.borrow_mut().take();
+            emitted
+        });
+        if !emitted {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "no logger installed",
+            ));
+        }
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+// Entry being replayed on this thread, if any
+pub(crate) fn raw_entry() -> Option<String> {
+    RAW_ENTRY
+        .try_with(|raw| raw.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+// Level matching a Stackdriver severity
+fn level(severity: &str) -> Level {
+    match severity {
+        "DEBUG" => Level::Debug,
+        "INFO" | "NOTICE" | "DEFAULT" => Level::Info,
+        "WARNING" => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_entries_are_written_unchanged() {
+        let buffer = crate::testing::Buffer::default();
+        let loggers = crate::build_loggers(&crate::builder().writer(buffer.clone()));
+
+        let entry =
+            r#"{"eventTime":"2024-03-01T12:00:00+00:00","insertId":"42","message":"Saved"}"#;
+        RAW_ENTRY.with(|raw| *raw.borrow_mut() = Some(entry.to_owned()));
+        loggers.verbose.log(
+            &log::Record::builder()
+                .args(format_args!("{}", entry))
+                .level(Level::Info)
+                .build(),
+        );
+        RAW_ENTRY.with(|raw| raw.borrow_mut().take());
+
+        assert_eq!(buffer.contents(), format!("{}\n", entry));
+    }
+
+    #[test]
+    fn severity_levels() {
+        assert_eq!(level("WARNING"), Level::Warn);
+        assert_eq!(level("NOTICE"), Level::Info);
+        assert_eq!(level("CRITICAL"), Level::Error);
+    }
+}
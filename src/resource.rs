@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, env, sync::RwLock};
+
+use {crate::Service, serde_json::Value};
+
+// Attributes detected from the environment, `None` when disabled
+static RESOURCE: RwLock<Option<BTreeMap<String, String>>> = RwLock::new(None);
+
+/// Emit resource attributes under a `resource` object, using OpenTelemetry semantic conventions.
+///
+/// This eases correlation with OpenTelemetry traces and metrics. Attributes are detected
+/// once, when calling this function :
+/// - `service.name` and `service.version`, from the service information
+/// - `k8s.pod.name` and `k8s.namespace.name`, from `HOSTNAME` and `POD_NAMESPACE` on Kubernetes
+/// - `faas.name` and `faas.version`, from `K_SERVICE` and `K_REVISION` on Cloud Run
+/// - any attribute listed in `OTEL_RESOURCE_ATTRIBUTES`, e.g. `cloud.region=europe-west1`
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_resource_attributes(true);
+/// ```
+pub fn set_resource_attributes(enabled: bool) {
+    let resource = if enabled { Some(detect()) } else { None };
+    if let Ok(mut current) = RESOURCE.write() {
+        *current = resource;
+    }
+}
+
+// Resource object for the current service, if enabled
+pub(crate) fn resource(service: Option<&Service>) -> Option<Value> {
+    let mut attributes = RESOURCE.read().ok()?.clone()?;
+
+    // Service information can change at runtime, don't cache it
+    if let Some(service) = service {
+        attributes.insert("service.name".to_owned(), service.name.clone());
+        attributes.insert("service.version".to_owned(), service.version.clone());
+    }
+
+    Some(
+        attributes
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect(),
+    )
+}
+
+// Platform the process runs on, as far as the environment tells
+pub(crate) fn platform() -> &'static str {
+    if env::var("KUBERNETES_SERVICE_HOST").is_ok() {
+        "kubernetes"
+    } else if env::var("K_SERVICE").is_ok() {
+        "cloud_run"
+    } else {
+        "unknown"
+    }
+}
+
+// Service information of a Cloud Run service, explicit variables take precedence
+pub(crate) fn cloud_run_service() -> Option<Service> {
+    let name = env::var("SERVICE_NAME")
+        .or_else(|_| env::var("K_SERVICE"))
+        .ok()?;
+    let version = env::var("SERVICE_VERSION")
+        .or_else(|_| env::var("K_REVISION"))
+        .unwrap_or_default();
+
+    Some(Service { name, version })
+}
+
+// Project the process runs in, as set by the deployment
+pub(crate) fn project_id() -> Option<String> {
+    ["GOOGLE_CLOUD_PROJECT", "GCP_PROJECT", "GCLOUD_PROJECT"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|p| !p.is_empty()))
+}
+
+// Deployment environment, e.g. `prod`, as set by the deployment
+pub(crate) fn environment() -> Option<String> {
+    env::var("DEPLOYMENT_ENVIRONMENT")
+        .ok()
+        .or_else(|| {
+            let attributes = env::var("OTEL_RESOURCE_ATTRIBUTES").ok()?;
+            let environment = parse_attributes(&attributes)
+                .find(|(key, _)| key == "deployment.environment")
+                .map(|(_, value)| value);
+            environment
+        })
+        .filter(|environment| !environment.is_empty())
+}
+
+fn detect() -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    let mut detect_var = |key: &str, var: &str| {
+        if let Ok(value) = env::var(var) {
+            attributes.insert(key.to_owned(), value);
+        }
+    };
+
+    if env::var("KUBERNETES_SERVICE_HOST").is_ok() {
+        detect_var("k8s.pod.name", "HOSTNAME");
+        detect_var("k8s.namespace.name", "POD_NAMESPACE");
+    }
+    detect_var("faas.name", "K_SERVICE");
+    detect_var("faas.version", "K_REVISION");
+
+    // Explicit attributes take precedence over detected ones
+    if let Ok(explicit) = env::var("OTEL_RESOURCE_ATTRIBUTES") {
+        attributes.extend(parse_attributes(&explicit));
+    }
+
+    attributes
+}
+
+// Parse the `key1=value1,key2=value2` format of OTEL_RESOURCE_ATTRIBUTES
+fn parse_attributes(input: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    input.split(',').filter_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        let key = key.trim();
+        if key.is_empty() {
+            return None;
+        }
+
+        Some((key.to_owned(), value.trim().to_owned()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otel_resource_attributes() {
+        let attributes: BTreeMap<_, _> =
+            parse_attributes("cloud.region=europe-west1, deployment.environment=prod,=x,broken")
+                .collect();
+
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["cloud.region"], "europe-west1");
+        assert_eq!(attributes["deployment.environment"], "prod");
+    }
+}
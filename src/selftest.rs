@@ -0,0 +1,146 @@
+use std::{
+    backtrace::Backtrace,
+    io::{self, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::Level;
+
+use crate::{logger, Context, HttpRequest};
+
+/// Outcome of [`selftest`].
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// Number of entries emitted, one per severity
+    pub entries: usize,
+
+    /// Sinks the entries were written to
+    pub sinks: Vec<SinkReport>,
+}
+
+impl SelfTestReport {
+    /// Whether every sink accepted the entries.
+    pub fn passed(&self) -> bool {
+        self.sinks.iter().all(SinkReport::accepted)
+    }
+}
+
+/// Outcome of [`selftest`] for a sink.
+#[derive(Debug)]
+pub struct SinkReport {
+    /// `stdout`, `stderr` or `writer` for a custom writer
+    pub name: &'static str,
+
+    /// Why the sink didn't accept the entries, if it didn't
+    pub error: Option<io::Error>,
+}
+
+impl SinkReport {
+    /// Whether the entries were written and flushed without error.
+    pub fn accepted(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Emit one entry at each severity and report which sinks accepted them.
+///
+/// The entries carry representative fields: a `selftest` field, trace correlation when a
+/// trace project is set, an `httpRequest` and, for the `ERROR` entry, a stack trace. They
+/// are emitted regardless of filters, so operators can check an environment's log pipeline
+/// end-to-end after a deployment, e.g. from a `selftest` subcommand.
+/// Fails if no logger is installed.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+///
+/// let report = stackdriver_logger::selftest().unwrap();
+/// for sink in &report.sinks {
+///     println!("{}: {}", sink.name, if sink.accepted() { "ok" } else { "failed" });
+/// }
+/// assert!(report.passed());
+/// ```
+pub fn selftest() -> io::Result<SelfTestReport> {
+    let sinks = logger::sinks()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no logger installed"))?;
+    let failures: Vec<usize> = sinks
+        .iter()
+        .map(|sink| sink.writer.as_ref().map_or(0, |w| w.failures()))
+        .collect();
+
+    let levels = [
+        Level::Trace,
+        Level::Debug,
+        Level::Info,
+        Level::Warn,
+        Level::Error,
+    ];
+    test_context().scope(|| {
+        for level in levels {
+            match level {
+                Level::Error => logger::self_log(
+                    level,
+                    format_args!(
+                        "Logging self-test, {} entry\n{}",
+                        level,
+                        Backtrace::force_capture()
+                    ),
+                ),
+                _ => logger::self_log(level, format_args!("Logging self-test, {} entry", level)),
+            }
+        }
+    });
+
+    let sinks = sinks
+        .into_iter()
+        .zip(failures)
+        .map(|(sink, failures)| SinkReport {
+            name: sink.name,
+            error: check(sink, failures).err(),
+        })
+        .collect();
+
+    Ok(SelfTestReport {
+        entries: levels.len(),
+        sinks,
+    })
+}
+
+// Flush a sink, and make sure none of the entries failed to be written
+fn check(sink: logger::Sink, failures: usize) -> io::Result<()> {
+    let mut writer = match sink.writer {
+        Some(writer) => writer,
+        None if sink.name == "stdout" => return io::stdout().flush(),
+        None => return io::stderr().flush(),
+    };
+
+    writer.flush()?;
+    match writer.failures().saturating_sub(failures) {
+        0 => Ok(()),
+        failed => Err(io::Error::other(format!(
+            "{} entries could not be written",
+            failed
+        ))),
+    }
+}
+
+// Fields of the self-test entries, ids are unique per run
+fn test_context() -> Context {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let request = HttpRequest {
+        method: Some("GET".to_owned()),
+        url: Some("/selftest".to_owned()),
+        status: Some(200),
+        latency: Some(Duration::from_millis(1)),
+        ..HttpRequest::default()
+    };
+
+    Context::current()
+        .with_field("selftest", true)
+        .with_field("trace_id", format!("{:032x}", now))
+        .with_field("span_id", format!("{:016x}", now as u64))
+        .with_http_request(request)
+}
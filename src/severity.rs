@@ -0,0 +1,171 @@
+use std::{fmt, str::FromStr, sync::RwLock};
+
+use log::Level;
+
+use crate::{context, Context, LogLevel};
+
+// Context or custom field overriding the severity of a record
+pub(crate) const SEVERITY_FIELD: &str = "severity";
+
+// Severity of each level, from ERROR to TRACE
+static MAPPING: RwLock<[Severity; 5]> = RwLock::new(DEFAULT_MAPPING);
+
+// Stackdriver doesn't have TRACE, it's emitted as DEBUG
+const DEFAULT_MAPPING: [Severity; 5] = [
+    Severity::Error,
+    Severity::Warning,
+    Severity::Info,
+    Severity::Debug,
+    Severity::Debug,
+];
+
+/// Severities of Cloud Logging, `log` levels only cover five of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// No assigned severity
+    Default,
+    /// Debug or trace information
+    Debug,
+    /// Routine information, such as ongoing status or performance
+    Info,
+    /// Normal but significant events, such as start up, shut down, or a configuration change
+    Notice,
+    /// Events that might cause problems
+    Warning,
+    /// Events that are likely to cause problems
+    Error,
+    /// Events that cause more severe problems or outages
+    Critical,
+    /// A person must take an action immediately
+    Alert,
+    /// One or more systems are unusable
+    Emergency,
+}
+
+impl Severity {
+    /// Name of the severity, as emitted in entries.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Default => "DEFAULT",
+            Severity::Debug => "DEBUG",
+            Severity::Info => "INFO",
+            Severity::Notice => "NOTICE",
+            Severity::Warning => "WARNING",
+            Severity::Error => "ERROR",
+            Severity::Critical => "CRITICAL",
+            Severity::Alert => "ALERT",
+            Severity::Emergency => "EMERGENCY",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = ();
+
+    // Case insensitive, `WARN` is accepted as well
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let severity = match s.to_ascii_uppercase().as_str() {
+            "DEFAULT" => Severity::Default,
+            "DEBUG" => Severity::Debug,
+            "INFO" => Severity::Info,
+            "NOTICE" => Severity::Notice,
+            "WARNING" | "WARN" => Severity::Warning,
+            "ERROR" => Severity::Error,
+            "CRITICAL" => Severity::Critical,
+            "ALERT" => Severity::Alert,
+            "EMERGENCY" => Severity::Emergency,
+            _ => return Err(()),
+        };
+        Ok(severity)
+    }
+}
+
+/// Change the severity emitted for a `log` level, to align severities with alerting rules.
+///
+/// By default `ERROR`, `WARN` and `INFO` keep their name, `WARN` is emitted as `WARNING`,
+/// `DEBUG` and `TRACE` are emitted as `DEBUG`. Severities set with [`with_severity`] take
+/// precedence. Only JSON output is affected.
+/// ## Usage
+/// ```rust
+/// use log::Level;
+/// use stackdriver_logger::Severity;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_severity_mapping(Level::Trace, Severity::Default);
+/// stackdriver_logger::set_severity_mapping(Level::Warn, Severity::Notice);
+/// ```
+pub fn set_severity_mapping(level: Level, severity: Severity) {
+    if let Ok(mut mapping) = MAPPING.write() {
+        mapping[level as usize - 1] = severity;
+    }
+}
+
+// Severity emitted for a level
+pub(crate) fn mapped(level: Level) -> Severity {
+    MAPPING
+        .read()
+        .map(|mapping| mapping[level as usize - 1])
+        .unwrap_or(DEFAULT_MAPPING[level as usize - 1])
+}
+
+/// Run a closure with the severity of its entries overridden, e.g. to emit `NOTICE` or
+/// `CRITICAL` entries alerting policies can match.
+///
+/// The `log` level still applies to filters, only the emitted severity changes. With the
+/// `customfields` feature, a `severity` key-value has the same effect on a single record, e.g.
+/// `error!(severity = "CRITICAL"; "Database unreachable")`. Unknown severities are ignored.
+/// Only JSON output is affected.
+/// ## Usage
+/// ```rust
+/// use log::{error, info};
+/// use stackdriver_logger::Severity;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::with_severity(Severity::Notice, || info!("Configuration reloaded"));
+/// stackdriver_logger::with_severity(Severity::Critical, || error!("Database unreachable"));
+/// ```
+pub fn with_severity<F: FnOnce() -> R, R>(severity: Severity, f: F) -> R {
+    Context::current()
+        .with_field(SEVERITY_FIELD, severity)
+        .scope(f)
+}
+
+// Severity to emit for a record, the override if any
+pub(crate) fn severity(record: &log::Record<'_>) -> String {
+    match context::field_value(record, SEVERITY_FIELD).and_then(|s| s.parse::<Severity>().ok()) {
+        Some(severity) => severity.as_str().to_owned(),
+        None => LogLevel(record.level()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overridden_severity() {
+        let record = log::Record::builder().level(Level::Info).build();
+        assert_eq!(severity(&record), "INFO");
+        assert_eq!(
+            with_severity(Severity::Notice, || severity(&record)),
+            "NOTICE"
+        );
+        Context::current()
+            .with_field(SEVERITY_FIELD, "bogus")
+            .scope(|| assert_eq!(severity(&record), "INFO"));
+        assert_eq!("critical".parse(), Ok(Severity::Critical));
+    }
+
+    #[test]
+    fn default_mapping() {
+        assert_eq!(mapped(Level::Error), Severity::Error);
+        assert_eq!(mapped(Level::Warn), Severity::Warning);
+        assert_eq!(mapped(Level::Trace), Severity::Debug);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{io, str::FromStr, thread};
+
+use log::LevelFilter;
+use signal_hook::{
+    consts::{SIGUSR1, SIGUSR2},
+    iterator::Signals,
+};
+
+use crate::{directives, logger};
+
+/// Step verbosity up on `SIGUSR1` and down on `SIGUSR2`.
+///
+/// Each signal moves the default level of the filters in effect by one step (e.g. `INFO` to
+/// `DEBUG`) and emits a `NOTICE` entry with the filters before and after. Per-module and field
+/// directives are kept as they are, `RUST_LOG` is restored once the default level gets back
+/// to its initial value.
+/// Requires the `signals` feature, Unix only.
+/// ## Usage
+/// ```rust,no_run
+/// stackdriver_logger::init();
+/// stackdriver_logger::watch_verbosity_signals().expect("Could not register signal handlers");
+/// ```
+/// Then from your terminal :
+/// ```sh
+/// kill -USR1 $(pidof my_app)
+/// ```
+pub fn watch_verbosity_signals() -> io::Result<()> {
+    let mut signals = Signals::new([SIGUSR1, SIGUSR2])?;
+
+    thread::Builder::new()
+        .name("stackdriver-signals".to_owned())
+        .spawn(move || {
+            for signal in signals.forever() {
+                let current = logger::current_filters();
+                let level = global_level(&current);
+                let (level, source) = match signal {
+                    SIGUSR1 => (more_verbose(level), "SIGUSR1"),
+                    _ => (less_verbose(level), "SIGUSR2"),
+                };
+
+                // The change is reported by the override
+                let filters = with_global_level(&current, level);
+                let default = logger::default_filters();
+                if filters == with_global_level(&default, global_level(&default)) {
+                    logger::set_override(None, source);
+                } else {
+                    logger::set_override(Some(&filters), source);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+// Level of targets without a directive of their own, field directives aside
+fn global_level(filters: &str) -> LevelFilter {
+    let (env_filters, _) = directives::split(filters);
+    let env_filters = env_filters.split('/').next().unwrap_or("");
+
+    let mut targets = false;
+    for directive in env_filters.split(',').map(str::trim) {
+        if let Ok(level) = LevelFilter::from_str(directive) {
+            return level;
+        }
+        targets |= !directive.is_empty();
+    }
+
+    // Same defaults as env_logger
+    if targets {
+        LevelFilter::Off
+    } else {
+        LevelFilter::Error
+    }
+}
+
+// Replace the global level of the filters, other directives are left untouched
+fn with_global_level(filters: &str, level: LevelFilter) -> String {
+    let (directives, regex) = match filters.split_once('/') {
+        Some((directives, regex)) => (directives, Some(regex)),
+        None => (filters, None),
+    };
+
+    let mut updated = vec![level.to_string().to_lowercase()];
+    updated.extend(
+        directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty() && LevelFilter::from_str(d).is_err())
+            .map(str::to_owned),
+    );
+
+    let mut filters = updated.join(",");
+    if let Some(regex) = regex {
+        filters.push('/');
+        filters.push_str(regex);
+    }
+    filters
+}
+
+fn more_verbose(level: LevelFilter) -> LevelFilter {
+    LevelFilter::iter()
+        .find(|l| *l > level)
+        .unwrap_or(LevelFilter::Trace)
+}
+
+fn less_verbose(level: LevelFilter) -> LevelFilter {
+    LevelFilter::iter()
+        .filter(|l| *l < level)
+        .last()
+        .unwrap_or(LevelFilter::Off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_steps() {
+        assert_eq!(more_verbose(LevelFilter::Info), LevelFilter::Debug);
+        assert_eq!(more_verbose(LevelFilter::Trace), LevelFilter::Trace);
+        assert_eq!(less_verbose(LevelFilter::Info), LevelFilter::Warn);
+        assert_eq!(less_verbose(LevelFilter::Off), LevelFilter::Off);
+    }
+
+    #[test]
+    fn step_keeps_directives() {
+        let filters = "info,hyper=warn,tenant=acme=trace";
+        assert_eq!(global_level(filters), LevelFilter::Info);
+
+        let quieter = with_global_level(filters, less_verbose(global_level(filters)));
+        assert_eq!(quieter, "warn,hyper=warn,tenant=acme=trace");
+        let louder = with_global_level(&quieter, LevelFilter::Debug);
+        assert_eq!(louder, "debug,hyper=warn,tenant=acme=trace");
+
+        assert_eq!(global_level("my_app=debug"), LevelFilter::Off);
+        assert_eq!(global_level(""), LevelFilter::Error);
+        assert_eq!(
+            with_global_level("my_app/foo", LevelFilter::Info),
+            "info,my_app/foo"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+// Locations aren't available with `nolocation`, links are never emitted
+#![cfg_attr(feature = "nolocation", allow(dead_code))]
+
+use std::sync::RwLock;
+
+use serde_json::{json, Value};
+
+// Template and revision used to link entries to their source code
+static SOURCE_LINK: RwLock<Option<SourceLink>> = RwLock::new(None);
+
+struct SourceLink {
+    template: String,
+    revision: String,
+}
+
+/// Emit a `sourceReference` field linking each entry to the line that logged it.
+///
+/// The template may contain `{sha}`, `{file}` and `{line}` placeholders, replaced with the
+/// given revision and the record location. The revision is usually injected at build time,
+/// e.g. from a `GIT_SHA` environment variable. Entries without a location have no link,
+/// this has no effect with the `nolocation` feature.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_source_link(
+///     "https://github.com/org/repo/blob/{sha}/{file}#L{line}",
+///     option_env!("GIT_SHA").unwrap_or("main"),
+/// );
+/// ```
+pub fn set_source_link(template: &str, revision: &str) {
+    if let Ok(mut current) = SOURCE_LINK.write() {
+        *current = Some(SourceLink {
+            template: template.to_owned(),
+            revision: revision.to_owned(),
+        });
+    }
+}
+
+// Source reference of a location, when enabled
+pub(crate) fn reference(file: Option<&str>, line: Option<u32>) -> Option<Value> {
+    let link = SOURCE_LINK.read().ok()?;
+    let link = link.as_ref()?;
+    let (file, line) = (file?, line?);
+
+    Some(json!({
+        "revisionId": link.revision,
+        "url": render(&link.template, &link.revision, file, line),
+    }))
+}
+
+fn render(template: &str, revision: &str, file: &str, line: u32) -> String {
+    template
+        .replace("{sha}", revision)
+        .replace("{file}", file.trim_start_matches("./"))
+        .replace("{line}", &line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        assert_eq!(
+            render(
+                "https://github.com/org/repo/blob/{sha}/{file}#L{line}",
+                "4bf92f35",
+                "./src/main.rs",
+                42
+            ),
+            "https://github.com/org/repo/blob/4bf92f35/src/main.rs#L42"
+        );
+    }
+}
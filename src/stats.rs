@@ -0,0 +1,48 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use log::Level;
+
+// When the logger was installed
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+// Entries emitted so far, indexed by level
+static EMITTED: [AtomicUsize; 5] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+// Entries dropped by the logger itself, e.g. over budget
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub(crate) fn record_emitted(level: Level) {
+    EMITTED[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn uptime() -> Duration {
+    STARTED.get().map(Instant::elapsed).unwrap_or_default()
+}
+
+pub(crate) fn emitted(level: Level) -> usize {
+    EMITTED[level as usize - 1].load(Ordering::Relaxed)
+}
+
+pub(crate) fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+
+use crate::{stats, OwnedRecord};
+
+// Streams currently subscribed to emitted records
+static SUBSCRIBERS: RwLock<Vec<Arc<Subscriber>>> = RwLock::new(Vec::new());
+
+// Skip snapshotting records when nobody listens
+static SUBSCRIBED: AtomicUsize = AtomicUsize::new(0);
+
+struct Subscriber {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    records: VecDeque<OwnedRecord>,
+    waker: Option<Waker>,
+}
+
+/// Emitted records, see [`record_stream`].
+///
+/// Records are removed from the stream once it is dropped. Requires the `stream` feature.
+pub struct RecordStream {
+    subscriber: Arc<Subscriber>,
+}
+
+/// Receive a snapshot of every emitted record as a `futures` `Stream`, to build custom
+/// shipping or processing on top of the default output.
+///
+/// Records are delivered once they have passed filters and sampling, with the fields of the
+/// context they were emitted in. Up to `capacity` records are buffered, further records are
+/// dropped for this stream instead of blocking the application and counted as dropped entries.
+/// The stream never ends. Requires the `stream` feature.
+/// ## Usage
+/// ```rust,ignore
+/// use futures::StreamExt;
+///
+/// stackdriver_logger::init();
+///
+/// let mut records = stackdriver_logger::record_stream(1024);
+/// tokio::spawn(async move {
+///     while let Some(record) = records.next().await {
+///         ship(record.to_json()).await;
+///     }
+/// });
+/// ```
+pub fn record_stream(capacity: usize) -> RecordStream {
+    let subscriber = Arc::new(Subscriber {
+        capacity: capacity.max(1),
+        state: Mutex::new(State::default()),
+    });
+
+    if let Ok(mut subscribers) = SUBSCRIBERS.write() {
+        subscribers.push(subscriber.clone());
+        SUBSCRIBED.store(subscribers.len(), Ordering::Relaxed);
+    }
+    RecordStream { subscriber }
+}
+
+// Hand an emitted record over to every stream
+pub(crate) fn publish(record: &log::Record<'_>) {
+    if SUBSCRIBED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let subscribers = match SUBSCRIBERS.read() {
+        Ok(subscribers) => subscribers.clone(),
+        Err(_) => return,
+    };
+    let snapshot = OwnedRecord::from_record(record);
+
+    for subscriber in subscribers {
+        let waker = match subscriber.state.lock() {
+            Ok(mut state) if state.records.len() < subscriber.capacity => {
+                state.records.push_back(snapshot.clone());
+                state.waker.take()
+            }
+            Ok(_) => {
+                stats::record_dropped();
+                None
+            }
+            Err(_) => None,
+        };
+
+        // Wake the task once the lock is released
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Stream for RecordStream {
+    type Item = OwnedRecord;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OwnedRecord>> {
+        let mut state = match self.subscriber.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Poll::Ready(None),
+        };
+
+        match state.records.pop_front() {
+            Some(record) => Poll::Ready(Some(record)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for RecordStream {
+    fn drop(&mut self) {
+        if let Ok(mut subscribers) = SUBSCRIBERS.write() {
+            subscribers.retain(|s| !Arc::ptr_eq(s, &self.subscriber));
+            SUBSCRIBED.store(subscribers.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    struct Flag(std::sync::atomic::AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn buffered_records() {
+        let flag = Arc::new(Flag(Default::default()));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut records = record_stream(1);
+        assert!(Pin::new(&mut records).poll_next(&mut cx).is_pending());
+
+        let record = |message| {
+            publish(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .target("record_stream")
+                    .build(),
+            )
+        };
+        record("first");
+        record("dropped");
+        assert!(flag.0.load(Ordering::SeqCst));
+
+        // Other tests may emit records concurrently, only look at ours
+        let mut messages = Vec::new();
+        while let Poll::Ready(Some(record)) = Pin::new(&mut records).poll_next(&mut cx) {
+            if record.target == "record_stream" {
+                messages.push(record.message);
+            }
+        }
+        assert_eq!(messages, ["first"]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+
+use log::Level;
+use serde_json::{json, Map, Value};
+
+use crate::{logger, severity::SEVERITY_FIELD, stats, Context, Severity};
+
+thread_local! {
+    // Numeric fields of the summary being emitted, context fields are only strings
+    static SUMMARY: RefCell<Option<Map<String, Value>>> = const { RefCell::new(None) };
+}
+
+/// Emit a final entry summarizing the process lifetime, for post-mortem timelines.
+///
+/// The entry reports the uptime, the number of entries emitted per severity, how many entries
+/// were dropped by the logger, and the given exit reason. It is emitted as a `NOTICE` entry,
+/// regardless of filters. Call this right before exiting, e.g. after receiving `SIGTERM` on
+/// Cloud Run, or see `WorkerGuard::with_shutdown_summary`.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// // ...
+/// stackdriver_logger::log_shutdown_summary("SIGTERM received");
+/// ```
+pub fn log_shutdown_summary(exit_reason: &str) {
+    let uptime = stats::uptime();
+    let context = Context::current()
+        .with_field("shutdown.exit_reason", exit_reason)
+        .with_field(SEVERITY_FIELD, Severity::Notice);
+
+    let mut summary = Map::new();
+    summary.insert(
+        "shutdown.uptime_seconds".to_owned(),
+        json!(uptime.as_secs()),
+    );
+    summary.insert("shutdown.dropped".to_owned(), json!(stats::dropped()));
+
+    let mut total = 0;
+    for level in [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ] {
+        let count = stats::emitted(level);
+        total += count;
+        summary.insert(
+            format!("shutdown.entries.{}", level.as_str().to_lowercase()),
+            json!(count),
+        );
+    }
+
+    SUMMARY.with(|current| *current.borrow_mut() = Some(summary));
+    context.scope(|| {
+        logger::self_log(
+            Level::Info,
+            format_args!(
+                "Shutting down after {}s, {} entries emitted: {}",
+                uptime.as_secs(),
+                total,
+                exit_reason
+            ),
+        )
+    });
+    SUMMARY.with(|current| current.borrow_mut().take());
+}
+
+// Add the numeric fields of the summary being emitted, if any
+pub(crate) fn insert_summary(payload: &mut Value) {
+    let _ = SUMMARY.try_with(|current| {
+        if let Some(summary) = current.borrow().as_ref() {
+            for (key, value) in summary {
+                payload[key] = value.clone();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing, WorkerGuard};
+
+    #[test]
+    fn summary_on_guard_drop() {
+        let output = testing::install_logger();
+        drop(WorkerGuard::new(Vec::new()).with_shutdown_summary("Guard dropped"));
+
+        let summary = testing::entries(&output)
+            .into_iter()
+            .find(|entry| entry["shutdown.exit_reason"] == "Guard dropped")
+            .unwrap();
+        assert_eq!(summary["severity"], "NOTICE");
+        assert!(summary["shutdown.uptime_seconds"].is_u64());
+        assert!(summary["shutdown.entries.info"].is_u64());
+    }
+}
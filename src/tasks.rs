@@ -0,0 +1,62 @@
+use std::sync::RwLock;
+
+type TaskIdHook = Box<dyn Fn() -> Option<String> + Send + Sync>;
+
+// Returns the id of the async task running on the current thread, if any
+static TASK_ID_HOOK: RwLock<Option<TaskIdHook>> = RwLock::new(None);
+
+/// Tag entries with the id of the async task emitting them, under a `taskId` field.
+///
+/// This helps correlating runtime stalls reported by diagnostic tools like `tokio-console`
+/// with the log lines of the offending task. The hook is called for each entry and should
+/// return `None` outside of a task. This crate doesn't depend on any runtime, with tokio
+/// the hook is typically built on `tokio::task::try_id`.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_task_id_hook(|| None);
+/// ```
+/// With tokio :
+/// ```rust,ignore
+/// stackdriver_logger::set_task_id_hook(|| tokio::task::try_id().map(|id| id.to_string()));
+/// ```
+pub fn set_task_id_hook<F>(hook: F)
+where
+    F: Fn() -> Option<String> + Send + Sync + 'static,
+{
+    if let Ok(mut current) = TASK_ID_HOOK.write() {
+        *current = Some(Box::new(hook));
+    }
+}
+
+// Id of the current task, when a hook is installed
+pub(crate) fn task_id() -> Option<String> {
+    TASK_ID_HOOK.read().ok()?.as_ref()?()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn task_id_hook() {
+        // Other tests run in parallel, only report an id on our own thread
+        set_task_id_hook(|| {
+            thread::current()
+                .name()
+                .filter(|name| *name == "task-id-test")
+                .map(|_| "42".to_owned())
+        });
+
+        let id = thread::Builder::new()
+            .name("task-id-test".to_owned())
+            .spawn(task_id)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(id.as_deref(), Some("42"));
+        assert_eq!(task_id(), None);
+    }
+}
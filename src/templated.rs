@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::Context;
+
+#[doc(hidden)]
+pub use log::Level;
+
+/// Context field holding the raw template of a message
+const TEMPLATE_FIELD: &str = "messageTemplate";
+
+/// Log a message template with named fields, keeping the raw template in the entry.
+///
+/// Fields are emitted like context fields and the template as a `messageTemplate` field,
+/// so entries can be grouped by exact template in queries. Placeholders use the `format!`
+/// syntax and refer to the fields by name.
+/// See also `error_t!`, `warn_t!`, `info_t!`, `debug_t!` and `trace_t!`.
+/// ## Usage
+/// ```rust
+/// use log::Level;
+///
+/// stackdriver_logger::init();
+///
+/// let id = 42;
+/// stackdriver_logger::log_t!(Level::Info, "user {user_id} logged in", user_id = id);
+/// stackdriver_logger::info_t!("user {user_id} logged out after {minutes}min", user_id = id, minutes = 12);
+/// ```
+#[macro_export]
+macro_rules! log_t {
+    ($level:expr, $template:literal $(, $key:ident = $value:expr)* $(,)?) => {{
+        let level: $crate::templated::Level = $level;
+        match ($($value,)*) {
+            ($($key,)*) => $crate::templated::log_template(
+                level,
+                (module_path!(), file!(), line!()),
+                $template,
+                format_args!($template),
+                &[$((stringify!($key), &$key as &dyn ::std::fmt::Display)),*],
+            ),
+        }
+    }};
+}
+
+/// Log an `ERROR` message template, see `log_t!`.
+#[macro_export]
+macro_rules! error_t {
+    ($($arg:tt)+) => { $crate::log_t!($crate::templated::Level::Error, $($arg)+) };
+}
+
+/// Log a `WARN` message template, see `log_t!`.
+#[macro_export]
+macro_rules! warn_t {
+    ($($arg:tt)+) => { $crate::log_t!($crate::templated::Level::Warn, $($arg)+) };
+}
+
+/// Log an `INFO` message template, see `log_t!`.
+#[macro_export]
+macro_rules! info_t {
+    ($($arg:tt)+) => { $crate::log_t!($crate::templated::Level::Info, $($arg)+) };
+}
+
+/// Log a `DEBUG` message template, see `log_t!`.
+#[macro_export]
+macro_rules! debug_t {
+    ($($arg:tt)+) => { $crate::log_t!($crate::templated::Level::Debug, $($arg)+) };
+}
+
+/// Log a `TRACE` message template, see `log_t!`.
+#[macro_export]
+macro_rules! trace_t {
+    ($($arg:tt)+) => { $crate::log_t!($crate::templated::Level::Trace, $($arg)+) };
+}
+
+#[doc(hidden)]
+pub fn log_template(
+    level: Level,
+    (module_path, file, line): (&'static str, &'static str, u32),
+    template: &'static str,
+    args: fmt::Arguments<'_>,
+    fields: &[(&str, &dyn fmt::Display)],
+) {
+    let metadata = log::Metadata::builder()
+        .level(level)
+        .target(module_path)
+        .build();
+    if !log::logger().enabled(&metadata) {
+        return;
+    }
+
+    let context = fields
+        .iter()
+        .fold(Context::current(), |context, (key, value)| {
+            context.with_field(*key, value)
+        })
+        .with_field(TEMPLATE_FIELD, template);
+
+    context.scope(|| {
+        log::logger().log(
+            &log::Record::builder()
+                .metadata(metadata)
+                .args(args)
+                .module_path_static(Some(module_path))
+                .file_static(Some(file))
+                .line(Some(line))
+                .build(),
+        )
+    });
+}
@@ -0,0 +1,159 @@
+use std::sync::RwLock;
+
+use log::Level;
+
+use crate::context;
+
+// Message templates, indexed by level
+static TEMPLATES: RwLock<[Option<String>; 5]> = RwLock::new([None, None, None, None, None]);
+
+// Field holding a short message, the formatted one is moved to `detail`
+static SUMMARY_KEY: RwLock<Option<String>> = RwLock::new(None);
+
+/// Render messages of a given level through a template.
+///
+/// This helps downstream alerting that only looks at the `message` string.
+/// The following placeholders are available :
+/// - `{message}`, the original message
+/// - `{level}`, `{target}`, `{module}`, `{file}` and `{line}`, from the record
+/// - any other `{name}`, replaced by the context or custom field with that name
+///
+/// Placeholders without a value are left empty, use `{{` and `}}` for literal braces.
+/// Only JSON output is affected.
+/// ## Usage
+/// ```rust
+/// use log::Level;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_message_template(Level::Warn, "[{target}] {message}");
+/// stackdriver_logger::set_message_template(Level::Error, "{message} (request {request_id})");
+/// ```
+pub fn set_message_template(level: Level, template: &str) {
+    if let Ok(mut templates) = TEMPLATES.write() {
+        templates[level as usize - 1] = Some(template.to_owned());
+    }
+}
+
+/// Use a field as a short `message`, moving the formatted text to a `detail` field.
+///
+/// Alert titles and error groups are built from `message`, a stable summary keeps them
+/// readable while the full text stays available. Records without the field are unchanged.
+/// The field is read from the record custom fields, or from the current context.
+/// Pass `None` to disable, this is the default.
+/// ## Usage
+/// ```rust
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_summary_key(Some("summary"));
+///
+/// // With the `customfields` feature
+/// // log::error!(summary = "Payment failed"; "Payment {} failed: card declined", 42);
+/// ```
+pub fn set_summary_key(key: Option<&str>) {
+    if let Ok(mut current) = SUMMARY_KEY.write() {
+        *current = key.map(str::to_owned);
+    }
+}
+
+// Replace the message with its summary when there is one, returning the full text as detail
+pub(crate) fn split_detail(record: &log::Record<'_>, message: String) -> (String, Option<String>) {
+    let summary = SUMMARY_KEY
+        .read()
+        .ok()
+        .and_then(|key| context::field_value(record, key.as_deref()?));
+
+    match summary {
+        Some(summary) if summary != message => (summary, Some(message)),
+        _ => (message, None),
+    }
+}
+
+// Render the record message through the template for its level, if any
+pub(crate) fn render_message(record: &log::Record<'_>) -> String {
+    let message = record.args().to_string();
+    let templates = match TEMPLATES.read() {
+        Ok(templates) => templates,
+        Err(_) => return message,
+    };
+
+    let template = match &templates[record.level() as usize - 1] {
+        Some(template) => template,
+        None => return message,
+    };
+
+    render(template, |name| match name {
+        "message" => Some(message.clone()),
+        "level" => Some(record.level().to_string()),
+        "target" => Some(record.target().to_owned()),
+        "module" => record.module_path().map(str::to_owned),
+        "file" => record.file().map(str::to_owned),
+        "line" => record.line().map(|l| l.to_string()),
+        _ => context::field_value(record, name),
+    })
+}
+
+fn render<F: Fn(&str) -> Option<String>>(template: &str, lookup: F) -> String {
+    let mut output = String::with_capacity(template.len() * 2);
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            output.push_str(&tail[..1]);
+            rest = &tail[2..];
+        } else if let (true, Some(end)) = (tail.starts_with('{'), tail.find('}')) {
+            if let Some(value) = lookup(&tail[1..end]) {
+                output.push_str(&value);
+            }
+            rest = &tail[end + 1..];
+        } else {
+            output.push_str(&tail[..1]);
+            rest = &tail[1..];
+        }
+    }
+    output.push_str(rest);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_rendering() {
+        let lookup = |name: &str| match name {
+            "message" => Some("Oops".to_owned()),
+            "target" => Some("my_app".to_owned()),
+            _ => None,
+        };
+
+        assert_eq!(render("[{target}] {message}", lookup), "[my_app] Oops");
+        assert_eq!(render("{message} ({missing})", lookup), "Oops ()");
+        assert_eq!(render("{{literal}} {message}", lookup), "{literal} Oops");
+        assert_eq!(render("unclosed {message", lookup), "unclosed {message");
+    }
+
+    #[test]
+    fn summarized_messages() {
+        let _globals = crate::testing::lock_globals();
+        let record = log::Record::builder().build();
+
+        set_summary_key(Some("test_summary"));
+        let summarized = crate::Context::new()
+            .with_field("test_summary", "Payment failed")
+            .scope(|| split_detail(&record, "Payment 42 declined".to_owned()));
+        let unchanged = split_detail(&record, "Payment 42 declined".to_owned());
+        set_summary_key(None);
+
+        assert_eq!(
+            summarized,
+            (
+                "Payment failed".to_owned(),
+                Some("Payment 42 declined".to_owned())
+            )
+        );
+        assert_eq!(unchanged, ("Payment 42 declined".to_owned(), None));
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    io,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+// Held by tests changing global options, and by those comparing whole entries
+static GLOBALS: Mutex<()> = Mutex::new(());
+
+// Writer keeping everything written to it, clones share the same output
+#[derive(Clone, Default)]
+pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+impl io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Serialize tests depending on global options, a failed test doesn't block the others
+pub(crate) fn lock_globals() -> MutexGuard<'static, ()> {
+    GLOBALS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Install the global logger once for every test, returns its JSON output
+pub(crate) fn install_logger() -> Buffer {
+    static OUTPUT: OnceLock<Buffer> = OnceLock::new();
+    OUTPUT
+        .get_or_init(|| {
+            let output = Buffer::default();
+            crate::builder()
+                .filters("trace")
+                .writer(output.clone())
+                .init();
+            output
+        })
+        .clone()
+}
+
+// Entries written by the global logger
+pub(crate) fn entries(output: &Buffer) -> Vec<serde_json::Value> {
+    output
+        .contents()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+// Entries written by the global logger with the given message
+pub(crate) fn entries_with_message(output: &Buffer, message: &str) -> Vec<serde_json::Value> {
+    entries(output)
+        .into_iter()
+        .filter(|entry| entry["message"] == message)
+        .collect()
+}
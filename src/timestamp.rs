@@ -0,0 +1,216 @@
+use std::{cell::Cell, time::SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::clock;
+
+thread_local! {
+    // Time of a record emitted again, used instead of the current time, see `emitted_at`
+    static RECORD_TIME: Cell<Option<SystemTime>> = const { Cell::new(None) };
+}
+
+// Key of the timestamp in the default payload
+pub(crate) const DEFAULT_KEY: &str = "eventTime";
+
+/// How the timestamp of an entry is represented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC3339 string, this is the default
+    #[default]
+    Rfc3339,
+
+    /// Object with integer `seconds` and `nanos`, like the `Timestamp` of the LogEntry API
+    Object,
+
+    /// Integer `timestampSeconds` and `timestampNanos` fields, parsed by the structured logging
+    /// agent without string parsing. The timestamp key is ignored
+    Fields,
+}
+
+/// Fractional digits of the entry timestamp.
+///
+/// Extra digits are truncated, in every `TimestampFormat`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    /// As many digits as needed out of 0, 3, 6 or 9, this is the default
+    #[default]
+    Auto,
+
+    /// Whole seconds
+    Seconds,
+
+    /// 3 digits
+    Millis,
+
+    /// 6 digits
+    Micros,
+
+    /// 9 digits
+    Nanos,
+}
+
+impl TimestampPrecision {
+    // Fractional digits kept, all of them when automatic
+    fn digits(self) -> u32 {
+        match self {
+            Self::Seconds => 0,
+            Self::Millis => 3,
+            Self::Micros => 6,
+            Self::Auto | Self::Nanos => 9,
+        }
+    }
+}
+
+// Emit the records logged within the closure as if they were logged at `time`
+pub(crate) fn emitted_at<F: FnOnce() -> R, R>(time: SystemTime, f: F) -> R {
+    let previous = RECORD_TIME.with(|current| current.replace(Some(time)));
+    let result = f();
+    RECORD_TIME.with(|current| current.set(previous));
+    result
+}
+
+// Time of the record being formatted, the current time unless emitted again
+pub(crate) fn record_time() -> SystemTime {
+    RECORD_TIME
+        .try_with(Cell::get)
+        .ok()
+        .flatten()
+        .unwrap_or_else(SystemTime::now)
+}
+
+// Move the timestamp of a payload to the given key, format and precision.
+// Every representation is derived from `time`, the time the payload was built at.
+pub(crate) fn reshape(
+    payload: &mut Value,
+    time: SystemTime,
+    key: &str,
+    format: TimestampFormat,
+    precision: TimestampPrecision,
+) {
+    if key == DEFAULT_KEY
+        && format == TimestampFormat::Rfc3339
+        && precision == TimestampPrecision::Auto
+    {
+        return;
+    }
+
+    // Stages may have removed the timestamp
+    let timestamp = match payload.as_object_mut().and_then(|p| p.remove(DEFAULT_KEY)) {
+        Some(timestamp) => timestamp,
+        None => return,
+    };
+
+    let digits = precision.digits();
+    match format {
+        TimestampFormat::Rfc3339 => {
+            payload[key] = match precision {
+                TimestampPrecision::Auto => timestamp,
+                _ => json!(clock::to_rfc3339_fixed(time, digits)),
+            }
+        }
+        TimestampFormat::Object => {
+            let (seconds, nanos) = truncate(time, digits);
+            payload[key] = json!({ "seconds": seconds, "nanos": nanos })
+        }
+        TimestampFormat::Fields => {
+            let (seconds, nanos) = truncate(time, digits);
+            payload["timestampSeconds"] = json!(seconds);
+            payload["timestampNanos"] = json!(nanos);
+        }
+    }
+}
+
+// Seconds and nanoseconds since the Unix epoch, with nanoseconds past `digits` dropped
+fn truncate(time: SystemTime, digits: u32) -> (i64, u32) {
+    let (seconds, nanos) = clock::unix(time);
+    (seconds, nanos - nanos % 10u32.pow(9 - digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn reshape_timestamp() {
+        let time = SystemTime::UNIX_EPOCH + Duration::new(1709294400, 250_000_000);
+        let payload = json!({ "eventTime": "2024-03-01T12:00:00.250+00:00", "message": "Info!" });
+
+        let mut renamed = payload.clone();
+        reshape(
+            &mut renamed,
+            time,
+            "time",
+            TimestampFormat::Rfc3339,
+            TimestampPrecision::Auto,
+        );
+        assert_eq!(
+            renamed,
+            json!({ "time": "2024-03-01T12:00:00.250+00:00", "message": "Info!" })
+        );
+
+        let mut object = payload.clone();
+        reshape(
+            &mut object,
+            time,
+            "timestamp",
+            TimestampFormat::Object,
+            TimestampPrecision::Auto,
+        );
+        assert_eq!(
+            object,
+            json!({
+                "timestamp": { "seconds": 1709294400, "nanos": 250000000 },
+                "message": "Info!",
+            })
+        );
+
+        let mut fields = payload.clone();
+        reshape(
+            &mut fields,
+            time,
+            DEFAULT_KEY,
+            TimestampFormat::Fields,
+            TimestampPrecision::Auto,
+        );
+        assert_eq!(
+            fields,
+            json!({
+                "timestampSeconds": 1709294400,
+                "timestampNanos": 250000000,
+                "message": "Info!",
+            })
+        );
+
+        let mut seconds = payload.clone();
+        reshape(
+            &mut seconds,
+            time,
+            DEFAULT_KEY,
+            TimestampFormat::Rfc3339,
+            TimestampPrecision::Seconds,
+        );
+        assert_eq!(seconds["eventTime"], "2024-03-01T12:00:00+00:00");
+
+        let mut micros = payload.clone();
+        reshape(
+            &mut micros,
+            time,
+            DEFAULT_KEY,
+            TimestampFormat::Rfc3339,
+            TimestampPrecision::Micros,
+        );
+        assert_eq!(micros["eventTime"], "2024-03-01T12:00:00.250000+00:00");
+
+        let mut truncated = payload.clone();
+        reshape(
+            &mut truncated,
+            time,
+            DEFAULT_KEY,
+            TimestampFormat::Fields,
+            TimestampPrecision::Seconds,
+        );
+        assert_eq!(truncated["timestampNanos"], 0);
+    }
+}
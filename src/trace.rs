@@ -0,0 +1,341 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+use log::Level;
+use serde_json::{json, Value};
+
+use crate::{context, Context};
+
+// Project owning the traces, required to build trace resource names
+static TRACE_PROJECT: RwLock<Option<String>> = RwLock::new(None);
+
+// Only emit DEBUG and TRACE records of sampled traces
+static SAMPLED_VERBOSITY: AtomicBool = AtomicBool::new(false);
+
+// Context or custom field holding the trace id of a record
+const TRACE_FIELD: &str = "trace_id";
+
+// Context or custom field holding the span id of a record
+const SPAN_FIELD: &str = "span_id";
+
+// Context or custom field telling whether the trace was sampled
+const SAMPLED_FIELD: &str = "trace_sampled";
+
+// Special field grouping entries with their trace in the Cloud Console
+const TRACE_KEY: &str = "logging.googleapis.com/trace";
+
+// Special field nesting entries under their span
+const SPAN_KEY: &str = "logging.googleapis.com/spanId";
+
+// Special field telling whether the trace was sampled
+const SAMPLED_KEY: &str = "logging.googleapis.com/trace_sampled";
+
+/// Group entries with their trace, by emitting `logging.googleapis.com/trace`.
+///
+/// The trace id is read from the `trace_id` custom field of a record, or from the current
+/// context, and emitted as `projects/<project_id>/traces/<trace_id>`. The `span_id` field
+/// is read the same way and emitted as `logging.googleapis.com/spanId`, so entries nest
+/// under their span in Cloud Trace. A `trace_sampled` field set to `true` or `false` is
+/// emitted as `logging.googleapis.com/trace_sampled`.
+/// ## Usage
+/// ```rust
+/// use log::info;
+/// use stackdriver_logger::Context;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_trace_project("my-project");
+///
+/// Context::current()
+///     .with_field("trace_id", "4bf92f3577b34da6a3ce929d0e0e4736")
+///     .with_field("span_id", "00f067aa0ba902b7")
+///     .scope(|| info!("Grouped with its trace"));
+/// ```
+pub fn set_trace_project(project_id: &str) {
+    if let Ok(mut project) = TRACE_PROJECT.write() {
+        *project = Some(project_id.to_owned());
+    }
+}
+
+/// Only emit `DEBUG` and `TRACE` entries of requests whose trace is sampled.
+///
+/// Detailed entries are then kept for exactly the requests that also have traces, at a
+/// fraction of the volume. The sampled flag is read from the `trace_sampled` field, see
+/// [`TraceContext::context`]. Verbose entries without a sampled trace are dropped, filters
+/// must still enable them, e.g. with `RUST_LOG=debug`.
+/// ## Usage
+/// ```rust
+/// use log::debug;
+/// use stackdriver_logger::TraceContext;
+///
+/// stackdriver_logger::init();
+/// stackdriver_logger::set_trace_sampled_verbosity(true);
+///
+/// let header = "105445aa7843bc8bf206b12000100000/1;o=1";
+/// if let Some(trace) = TraceContext::from_cloud_trace_header(header) {
+///     trace.context().scope(|| debug!("Only emitted for sampled traces"));
+/// }
+/// ```
+pub fn set_trace_sampled_verbosity(enabled: bool) {
+    SAMPLED_VERBOSITY.store(enabled, Ordering::Relaxed);
+}
+
+/// Trace correlation read from an incoming request, an `X-Cloud-Trace-Context` or a W3C
+/// `traceparent` header.
+///
+/// Feed it into the current context with [`TraceContext::context`] so entries logged while
+/// handling the request are grouped with its trace, see [`set_trace_project`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    /// Trace id, 32 hexadecimal characters.
+    pub trace_id: String,
+    /// Span id, 16 hexadecimal characters, if the header had one.
+    pub span_id: Option<String>,
+    /// Whether the trace was sampled, if the header said so.
+    pub sampled: Option<bool>,
+}
+
+impl TraceContext {
+    /// Parse an `X-Cloud-Trace-Context` header, formatted as `TRACE_ID/SPAN_ID;o=OPTIONS`.
+    ///
+    /// The span id of this header is decimal, it is converted to the hexadecimal form
+    /// expected by Cloud Logging. Returns `None` if the trace id isn't 32 hexadecimal characters.
+    /// ## Usage
+    /// ```rust
+    /// use log::info;
+    /// use stackdriver_logger::TraceContext;
+    ///
+    /// stackdriver_logger::init();
+    /// stackdriver_logger::set_trace_project("my-project");
+    ///
+    /// let header = "105445aa7843bc8bf206b12000100000/1;o=1";
+    /// if let Some(trace) = TraceContext::from_cloud_trace_header(header) {
+    ///     trace.context().scope(|| info!("Grouped with its trace"));
+    /// }
+    /// ```
+    pub fn from_cloud_trace_header(header: &str) -> Option<Self> {
+        let (ids, options) = match header.trim().split_once(';') {
+            Some((ids, options)) => (ids, Some(options)),
+            None => (header.trim(), None),
+        };
+        let (trace_id, span_id) = match ids.split_once('/') {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (ids, None),
+        };
+
+        if trace_id.len() != 32 || !trace_id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let span_id = span_id
+            .and_then(|id| id.parse::<u64>().ok())
+            .map(|id| format!("{:016x}", id));
+        let sampled = options
+            .and_then(|options| options.trim().strip_prefix("o="))
+            .and_then(|flags| match flags {
+                "1" => Some(true),
+                "0" => Some(false),
+                _ => None,
+            });
+
+        Some(Self {
+            trace_id: trace_id.to_ascii_lowercase(),
+            span_id,
+            sampled,
+        })
+    }
+
+    /// Parse a W3C `traceparent` header, formatted as `VERSION-TRACE_ID-SPAN_ID-FLAGS`.
+    ///
+    /// Returns `None` if the header is malformed, or if the trace or span id is all zeros.
+    /// ## Usage
+    /// ```rust
+    /// use stackdriver_logger::TraceContext;
+    ///
+    /// let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    /// let trace = TraceContext::from_traceparent(header).unwrap();
+    /// assert_eq!(trace.span_id.as_deref(), Some("00f067aa0ba902b7"));
+    /// assert_eq!(trace.sampled, Some(true));
+    /// ```
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        // Version 00 has exactly four parts, later versions may append more
+        if version.len() != 2 || version.eq_ignore_ascii_case("ff") {
+            return None;
+        }
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+
+        let hex = |id: &str, len: usize| {
+            id.len() == len
+                && id.chars().all(|c| c.is_ascii_hexdigit())
+                && id.chars().any(|c| c != '0')
+        };
+        if !hex(trace_id, 32) || !hex(span_id, 16) || flags.len() != 2 {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        Some(Self {
+            trace_id: trace_id.to_ascii_lowercase(),
+            span_id: Some(span_id.to_ascii_lowercase()),
+            sampled: Some(flags & 1 == 1),
+        })
+    }
+
+    /// The current context with the trace correlation fields set.
+    pub fn context(&self) -> Context {
+        let mut context = Context::current().with_field(TRACE_FIELD, &self.trace_id);
+        if let Some(span_id) = &self.span_id {
+            context = context.with_field(SPAN_FIELD, span_id);
+        }
+        if let Some(sampled) = self.sampled {
+            context = context.with_field(SAMPLED_FIELD, sampled);
+        }
+        context
+    }
+}
+
+// Add the trace special fields of a record, if any
+pub(crate) fn insert_trace(payload: &mut Value, record: &log::Record<'_>) {
+    let project = match TRACE_PROJECT.read() {
+        Ok(project) => project.clone(),
+        Err(_) => return,
+    };
+
+    let project = match project {
+        Some(project) => project,
+        None => return,
+    };
+
+    if let Some(trace_id) = context::field_value(record, TRACE_FIELD) {
+        payload[TRACE_KEY] = json!(format!("projects/{}/traces/{}", project, trace_id));
+    }
+    if let Some(span_id) = context::field_value(record, SPAN_FIELD) {
+        payload[SPAN_KEY] = json!(span_id);
+    }
+    if let Some(sampled) = trace_sampled(record) {
+        payload[SAMPLED_KEY] = json!(sampled);
+    }
+}
+
+// Whether the record is kept, verbose ones need a sampled trace if enabled
+pub(crate) fn sampled(record: &log::Record<'_>) -> bool {
+    record.level() < Level::Debug
+        || !SAMPLED_VERBOSITY.load(Ordering::Relaxed)
+        || trace_sampled(record) == Some(true)
+}
+
+// Sampled flag of the record trace, if any
+fn trace_sampled(record: &log::Record<'_>) -> Option<bool> {
+    match context::field_value(record, SAMPLED_FIELD)?.as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_resource_name() {
+        let _globals = crate::testing::lock_globals();
+        set_trace_project("my-project");
+        let record = log::Record::builder().build();
+
+        let mut payload = json!({});
+        crate::Context::new()
+            .with_field(TRACE_FIELD, "4bf92f35")
+            .with_field(SPAN_FIELD, "00f067aa")
+            .with_field(SAMPLED_FIELD, true)
+            .scope(|| insert_trace(&mut payload, &record));
+        *TRACE_PROJECT.write().unwrap() = None;
+
+        assert_eq!(
+            payload,
+            json!({
+                "logging.googleapis.com/trace": "projects/my-project/traces/4bf92f35",
+                "logging.googleapis.com/spanId": "00f067aa",
+                "logging.googleapis.com/trace_sampled": true,
+            })
+        );
+    }
+
+    #[test]
+    fn sampled_verbosity() {
+        let _globals = crate::testing::lock_globals();
+        let record = |level| log::Record::builder().level(level).build();
+        let sampled = |trace: &str, level| {
+            let trace = TraceContext::from_cloud_trace_header(trace).unwrap();
+            trace.context().scope(|| super::sampled(&record(level)))
+        };
+
+        set_trace_sampled_verbosity(true);
+        let kept = [
+            sampled("105445aa7843bc8bf206b12000100000/1;o=1", Level::Debug),
+            sampled("105445aa7843bc8bf206b12000100000/1;o=0", Level::Debug),
+            sampled("105445aa7843bc8bf206b12000100000/1;o=0", Level::Info),
+            super::sampled(&record(Level::Trace)),
+        ];
+        set_trace_sampled_verbosity(false);
+
+        assert_eq!(kept, [true, false, true, false]);
+        assert!(super::sampled(&record(Level::Trace)));
+    }
+
+    #[test]
+    fn cloud_trace_header() {
+        let trace = TraceContext::from_cloud_trace_header("105445AA7843bc8bf206b12000100000/1;o=1");
+        assert_eq!(
+            trace,
+            Some(TraceContext {
+                trace_id: "105445aa7843bc8bf206b12000100000".to_owned(),
+                span_id: Some("0000000000000001".to_owned()),
+                sampled: Some(true),
+            })
+        );
+
+        let trace = TraceContext::from_cloud_trace_header("105445aa7843bc8bf206b12000100000");
+        assert_eq!(trace.and_then(|t| t.span_id), None);
+
+        assert_eq!(TraceContext::from_cloud_trace_header("/1;o=1"), None);
+        assert_eq!(TraceContext::from_cloud_trace_header("not-a-trace/1"), None);
+        assert_eq!(
+            TraceContext::from_cloud_trace_header("105445aa/1;o=1"),
+            None
+        );
+    }
+
+    #[test]
+    fn traceparent_header() {
+        let trace = TraceContext::from_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+        );
+        assert_eq!(
+            trace,
+            Some(TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                span_id: Some("00f067aa0ba902b7".to_owned()),
+                sampled: Some(false),
+            })
+        );
+
+        let zeros = "00-00000000000000000000000000000000-00f067aa0ba902b7-01";
+        assert_eq!(TraceContext::from_traceparent(zeros), None);
+        let invalid = "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert_eq!(TraceContext::from_traceparent(invalid), None);
+        assert_eq!(
+            TraceContext::from_traceparent("00-4bf92f35-00f067aa-01"),
+            None
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fmt, panic::Location};
+
+use log::Level;
+
+/// Log a message located at the caller, for logging functions wrapping this crate.
+///
+/// Log macros report the location of the wrapper. When the wrapper is annotated with
+/// `#[track_caller]` and calls this function, `reportLocation` points at the code calling
+/// the wrapper instead. Unlike `set_caller_skip_list`, this works without debug symbols.
+/// The module path of the caller isn't known, only its file and line are reported.
+/// See also `error_tracked`, `warn_tracked`, `info_tracked`, `debug_tracked` and `trace_tracked`.
+/// ## Usage
+/// ```rust
+/// use log::Level;
+///
+/// #[track_caller]
+/// fn audit(action: &str) {
+///     stackdriver_logger::log_tracked(Level::Info, "audit", format_args!("audit: {}", action));
+/// }
+///
+/// stackdriver_logger::init();
+/// audit("user deleted"); // Reported at this line
+/// ```
+#[track_caller]
+pub fn log_tracked(level: Level, target: &str, args: fmt::Arguments<'_>) {
+    let metadata = log::Metadata::builder().level(level).target(target).build();
+    if !log::logger().enabled(&metadata) {
+        return;
+    }
+
+    let location = Location::caller();
+    log::logger().log(
+        &log::Record::builder()
+            .metadata(metadata)
+            .args(args)
+            .file_static(Some(location.file()))
+            .line(Some(location.line()))
+            .build(),
+    );
+}
+
+/// Log an `ERROR` message located at the caller, see `log_tracked`.
+#[track_caller]
+pub fn error_tracked(target: &str, args: fmt::Arguments<'_>) {
+    log_tracked(Level::Error, target, args);
+}
+
+/// Log a `WARN` message located at the caller, see `log_tracked`.
+#[track_caller]
+pub fn warn_tracked(target: &str, args: fmt::Arguments<'_>) {
+    log_tracked(Level::Warn, target, args);
+}
+
+/// Log an `INFO` message located at the caller, see `log_tracked`.
+#[track_caller]
+pub fn info_tracked(target: &str, args: fmt::Arguments<'_>) {
+    log_tracked(Level::Info, target, args);
+}
+
+/// Log a `DEBUG` message located at the caller, see `log_tracked`.
+#[track_caller]
+pub fn debug_tracked(target: &str, args: fmt::Arguments<'_>) {
+    log_tracked(Level::Debug, target, args);
+}
+
+/// Log a `TRACE` message located at the caller, see `log_tracked`.
+#[track_caller]
+pub fn trace_tracked(target: &str, args: fmt::Arguments<'_>) {
+    log_tracked(Level::Trace, target, args);
+}
+
+#[cfg(all(test, not(feature = "nolocation")))]
+mod tests {
+    use crate::testing;
+
+    #[track_caller]
+    fn wrapper() {
+        super::info_tracked("tracked", format_args!("Located at the caller"));
+    }
+
+    #[test]
+    fn caller_location() {
+        let output = testing::install_logger();
+        let line = line!() + 1;
+        wrapper();
+
+        let entries = testing::entries_with_message(&output, "Located at the caller");
+        assert_eq!(entries[0]["reportLocation"]["filePath"], file!());
+        assert_eq!(entries[0]["reportLocation"]["lineNumber"], line);
+    }
+}
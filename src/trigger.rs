@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::logger;
+
+// Short enough for an operator waiting on the change, cheap enough to run forever
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Used when the trigger file exists but is empty
+const DEFAULT_TRIGGER_FILTERS: &str = "debug";
+
+/// Watch a trigger file switching the effective log level of a running process.
+///
+/// While the file exists, its content replaces the `RUST_LOG` filters, using the same syntax.
+/// An empty file enables `debug` logs for everything. Removing the file restores `RUST_LOG`.
+/// Filters set by other means, e.g. the admin endpoint, are only replaced when the file changes.
+/// This makes it possible to toggle verbose logging on a running pod with `kubectl exec`.
+/// Changes are reported by a `NOTICE` entry with the filters before and after.
+/// ## Usage
+/// ```rust,no_run
+/// stackdriver_logger::init();
+/// stackdriver_logger::watch_verbosity_trigger("/etc/debug-logging");
+/// ```
+/// Then from your terminal :
+/// ```sh
+/// kubectl exec my-pod -- sh -c 'echo "my_app=trace" > /etc/debug-logging'
+/// kubectl exec my-pod -- rm /etc/debug-logging
+/// ```
+pub fn watch_verbosity_trigger<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+
+    let source = format!("trigger {}", path.display());
+    // Without the file, keep any override set by other means
+    let mut last = read_trigger(&path);
+    if last.is_some() {
+        logger::set_override(last.as_deref(), &source);
+    }
+
+    thread::Builder::new()
+        .name("stackdriver-trigger".to_owned())
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = read_trigger(&path);
+            if current != last {
+                logger::set_override(current.as_deref(), &source);
+                last = current;
+            }
+        })
+        .expect("Could not spawn stackdriver_logger trigger watcher");
+}
+
+fn read_trigger(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let content = content.trim();
+
+    if content.is_empty() {
+        Some(DEFAULT_TRIGGER_FILTERS.to_owned())
+    } else {
+        Some(content.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_trigger_keeps_override() {
+        let _globals = crate::testing::lock_globals();
+        let path = std::env::temp_dir().join("stackdriver-logger-missing-trigger");
+
+        logger::set_override(Some("my_app=debug"), "test");
+        watch_verbosity_trigger(&path);
+        let filters = logger::current_filters();
+        logger::set_override(None, "test");
+
+        assert_eq!(filters, "my_app=debug");
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::stats;
+
+// Entries waiting for the writer thread, further entries are dropped
+const BUFFERED_ENTRIES: usize = 128 * 1024;
+
+// Writer shared by the default and verbose loggers
+#[derive(Clone)]
+pub(crate) struct SharedWriter {
+    inner: Arc<Mutex<Box<dyn Write + Send>>>,
+    // Failed writes, env_logger discards the errors
+    failures: Arc<AtomicUsize>,
+}
+
+impl SharedWriter {
+    pub(crate) fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Box::new(writer))),
+            failures: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Number of writes that failed so far
+    pub(crate) fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn count_failure<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if result.is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl fmt::Debug for SharedWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedWriter")
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.lock()?.write(buf);
+        self.count_failure(result)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let result = self.lock()?.write_all(buf);
+        self.count_failure(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+impl SharedWriter {
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, Box<dyn Write + Send>>> {
+        self.inner
+            .lock()
+            .map_err(|_| io::Error::other("log writer poisoned"))
+    }
+}
+
+enum Message {
+    Entry(Vec<u8>),
+    Flush(SyncSender<()>),
+    Shutdown,
+}
+
+// Hand entries over to a dedicated thread doing the actual writes.
+// env_logger writes each formatted entry at once, so messages are whole entries.
+pub(crate) struct NonBlocking {
+    sender: SyncSender<Message>,
+    // Used directly once the writer thread is stopped
+    writer: SharedWriter,
+}
+
+// Handle on the writer thread of a non-blocking writer
+#[derive(Clone)]
+pub(crate) struct Worker {
+    sender: SyncSender<Message>,
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl NonBlocking {
+    pub(crate) fn spawn(writer: SharedWriter) -> io::Result<(Self, Worker)> {
+        let (sender, receiver) = mpsc::sync_channel(BUFFERED_ENTRIES);
+        let mut thread_writer = writer.clone();
+        let handle = thread::Builder::new()
+            .name("stackdriver-logger-writer".to_owned())
+            .spawn(move || {
+                for message in receiver.iter() {
+                    match message {
+                        Message::Entry(entry) => {
+                            let _ = thread_writer.write_all(&entry);
+                        }
+                        Message::Flush(done) => {
+                            let _ = thread_writer.flush();
+                            let _ = done.send(());
+                        }
+                        Message::Shutdown => break,
+                    }
+                }
+
+                // Entries sent while shutting down
+                for message in receiver.try_iter() {
+                    if let Message::Entry(entry) = message {
+                        let _ = thread_writer.write_all(&entry);
+                    }
+                }
+                let _ = thread_writer.flush();
+            })?;
+
+        let worker = Worker {
+            sender: sender.clone(),
+            handle: Arc::new(Mutex::new(Some(handle))),
+        };
+        Ok((Self { sender, writer }, worker))
+    }
+}
+
+impl Worker {
+    // Stop the writer thread once pending entries are written, later entries are written
+    // from the calling thread
+    pub(crate) fn shutdown(&self) {
+        let handle = match self.handle.lock() {
+            Ok(mut handle) => handle.take(),
+            Err(_) => return,
+        };
+
+        if let Some(handle) = handle {
+            let _ = self.sender.send(Message::Shutdown);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Write for NonBlocking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.sender.try_send(Message::Entry(buf.to_vec())) {
+            Ok(()) => Ok(buf.len()),
+            // Never block the caller, drop the entry if the writer thread can't keep up
+            Err(TrySendError::Full(_)) => {
+                stats::record_dropped();
+                Ok(buf.len())
+            }
+            Err(TrySendError::Disconnected(_)) => self.writer.write(buf),
+        }
+    }
+
+    // Wait for the writer thread to write and flush every pending entry
+    fn flush(&mut self) -> io::Result<()> {
+        let (done, flushed) = mpsc::sync_channel(1);
+        if self.sender.send(Message::Flush(done)).is_err() || flushed.recv().is_err() {
+            return self.writer.flush();
+        }
+        Ok(())
+    }
+}
+
+/// Flushes pending entries and stops background writer threads when dropped.
+///
+/// Returned by `Builder::init_guarded`, keep it alive until the end of `main`. Entries
+/// logged after the guard is dropped are written from the calling thread.
+#[must_use = "entries are flushed when the guard is dropped"]
+pub struct WorkerGuard {
+    workers: Vec<Worker>,
+    // Exit reason of the shutdown summary, if enabled
+    summary: Option<String>,
+}
+
+impl WorkerGuard {
+    pub(crate) fn new(workers: Vec<Worker>) -> Self {
+        Self {
+            workers,
+            summary: None,
+        }
+    }
+
+    /// Emit the shutdown summary with the given exit reason when the guard is dropped,
+    /// see `log_shutdown_summary`.
+    /// ## Usage
+    /// ```rust
+    /// let _guard = stackdriver_logger::builder()
+    ///     .init_guarded()
+    ///     .with_shutdown_summary("main returned");
+    /// ```
+    pub fn with_shutdown_summary<R: Into<String>>(mut self, exit_reason: R) -> Self {
+        self.summary = Some(exit_reason.into());
+        self
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if let Some(exit_reason) = &self.summary {
+            crate::log_shutdown_summary(exit_reason);
+        }
+        log::logger().flush();
+        for worker in &self.workers {
+            worker.shutdown();
+        }
+        #[cfg(all(unix, feature = "crashbuffer"))]
+        crate::close_crash_buffer();
+    }
+}
+
+/// Thresholds for batched output, see `Builder::batch`.
+#[derive(Clone, Copy, Debug)]
+pub struct Batch {
+    /// Write pending entries once they reach this many bytes, 64KiB by default
+    pub max_bytes: usize,
+
+    /// Write pending entries at least this often, 200ms by default. Shorter intervals than
+    /// 10ms are raised to 10ms, so the background thread doesn't spin.
+    pub interval: Duration,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024,
+            interval: Duration::from_millis(200),
+        }
+    }
+}
+
+// Shortest interval between idle batch writes
+const MIN_BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+struct BatchState {
+    buffer: Vec<u8>,
+    writer: SharedWriter,
+    last_write: Instant,
+}
+
+impl BatchState {
+    fn write_pending(&mut self) -> io::Result<()> {
+        self.last_write = Instant::now();
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.writer.write_all(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+}
+
+// Accumulate entries and write them at once, on size or time thresholds
+pub(crate) struct Batched {
+    batch: Batch,
+    state: Arc<Mutex<BatchState>>,
+}
+
+impl Batched {
+    pub(crate) fn new(writer: SharedWriter, mut batch: Batch) -> Self {
+        batch.interval = batch.interval.max(MIN_BATCH_INTERVAL);
+        let state = Arc::new(Mutex::new(BatchState {
+            buffer: Vec::with_capacity(batch.max_bytes),
+            writer,
+            last_write: Instant::now(),
+        }));
+
+        // Write idle batches from a background thread, stops once the writer is dropped.
+        // Without threads, batches are written by the next entry or an explicit flush.
+        let weak = Arc::downgrade(&state);
+        let _ = thread::Builder::new()
+            .name("stackdriver-logger-batch".to_owned())
+            .spawn(move || write_idle_batches(weak, batch.interval));
+
+        Self { batch, state }
+    }
+
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, BatchState>> {
+        self.state
+            .lock()
+            .map_err(|_| io::Error::other("log writer poisoned"))
+    }
+}
+
+fn write_idle_batches(state: Weak<Mutex<BatchState>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+        if let Ok(mut state) = state.lock() {
+            if state.last_write.elapsed() >= interval {
+                let _ = state.write_pending();
+            }
+        };
+    }
+}
+
+impl Write for Batched {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock()?;
+        state.buffer.extend_from_slice(buf);
+        if state.buffer.len() >= self.batch.max_bytes
+            || state.last_write.elapsed() >= self.batch.interval
+        {
+            state.write_pending()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.lock()?;
+        state.write_pending()?;
+        state.writer.flush()
+    }
+}
+
+impl Drop for Batched {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_batch_interval() {
+        let batch = Batch {
+            max_bytes: 1024,
+            interval: Duration::ZERO,
+        };
+        let batched = Batched::new(SharedWriter::new(io::sink()), batch);
+        assert_eq!(batched.batch.interval, MIN_BATCH_INTERVAL);
+    }
+}